@@ -0,0 +1,162 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+/*
+ * Throughput of messages forwarded to RORI over D-Bus: a private blocking connection per
+ * message (as the bridge did before) against Endpoint::forward_messages on the shared connection.
+ * Talks to a fake daemon on the session bus, or to the real one if RORI_BENCH_ACCOUNT and
+ * RORI_BENCH_RORI are set (RORI_BOT_DAEMON chooses its namespace)
+ */
+
+#[macro_use]
+extern crate criterion;
+extern crate dbus;
+extern crate rori_discord_bot;
+extern crate tokio;
+
+use criterion::{Criterion, Throughput};
+use dbus::blocking;
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::MatchRule;
+use rori_discord_bot::discord::DiscordMsg;
+use rori_discord_bot::rori::daemon::DaemonBus;
+use rori_discord_bot::rori::endpoint::Endpoint;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CString;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::unbounded_channel;
+
+const MESSAGES: u64 = 50;
+const TIMEOUT: Duration = Duration::from_secs(2);
+const FAKE_ACCOUNT: &str = "0123456789abcdef";
+const FAKE_RORI: &str = "fedcba9876543210fedcba9876543210fedcba98";
+
+/**
+ * Answer the calls of the bridge like the daemon, without sending anything
+ * @param bus namespace to serve
+ */
+fn serve_fake_daemon(bus: &'static DaemonBus) {
+    let conn = blocking::Connection::new_session().expect("No session bus");
+    conn.request_name(bus.name, false, true, true).expect("Can't own the daemon's name");
+    conn.start_receive(MatchRule::new_method_call(), Box::new(|msg, conn| {
+        let mut details = HashMap::new();
+        details.insert("Account.enable", "true");
+        details.insert("Account.alias", "bench");
+        details.insert("Account.username", "ring:0123456789abcdef0123456789abcdef01234567");
+        details.insert("Account.registrationStatus", "REGISTERED");
+        let reply = match msg.member().as_deref() {
+            Some("getAccountDetails") | Some("getVolatileAccountDetails") => msg.method_return().append1(details),
+            Some("sendTextMessage") => msg.method_return().append1(42u64),
+            _ => msg.error(&"org.freedesktop.DBus.Error.UnknownMethod".into(),
+                           &CString::new("Not faked").unwrap()),
+        };
+        let _ = conn.send(reply);
+        true
+    }));
+    loop {
+        conn.process(Duration::from_secs(1)).expect("Lost the session bus");
+    }
+}
+
+fn discord_msg(idx: u64) -> DiscordMsg {
+    let mut msg = DiscordMsg::new();
+    msg.id = idx.to_string();
+    msg.body = format!("bench {}", idx);
+    msg.author = String::from("bench");
+    msg.channel = String::from("1");
+    msg.datatype = String::from("text/plain");
+    msg
+}
+
+/**
+ * Forward a message by opening a private connection, like the bridge did for each message
+ * @param account
+ * @param rori
+ * @param msg
+ */
+fn forward_private(account: &str, rori: &str, msg: DiscordMsg) {
+    let bus = DaemonBus::current();
+    let conn = blocking::Connection::new_session().expect("No session bus");
+    let proxy = conn.with_proxy(bus.name, bus.configuration_path, TIMEOUT);
+    let mut payloads = HashMap::new();
+    payloads.insert("text/plain", &*msg.body);
+    payloads.insert("sa", &*msg.author);
+    payloads.insert("th", &*msg.id);
+    payloads.insert("ch", &*msg.channel);
+    let _: (u64,) = proxy.method_call(bus.configuration_iface, "sendTextMessage", (account, rori, payloads))
+        .expect("sendTextMessage fails");
+}
+
+fn forward_messages(c: &mut Criterion) {
+    let mut rt = tokio::runtime::Builder::new().basic_scheduler().enable_all().build().unwrap();
+    let account = env::var("RORI_BENCH_ACCOUNT").unwrap_or_default();
+    let rori = env::var("RORI_BENCH_RORI").unwrap_or_default();
+    let (account, rori) = if account.is_empty() || rori.is_empty() {
+        let bus = DaemonBus::current();
+        thread::spawn(move || serve_fake_daemon(bus));
+        // Let the fake daemon own its name
+        thread::sleep(Duration::from_millis(200));
+        (String::from(FAKE_ACCOUNT), String::from(FAKE_RORI))
+    } else {
+        rt.block_on(DaemonBus::select(&env::var("RORI_BOT_DAEMON").unwrap_or_default()))
+            .expect("No daemon");
+        (account, rori)
+    };
+
+    // The forwarding path of the bridge
+    let (user_say, user_text) = unbounded_channel();
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let endpoint = rt.block_on(Endpoint::init(&account, &rori, HashMap::new())).expect("Can't load the account");
+    rt.spawn(Endpoint::forward_messages(Arc::new(Mutex::new(endpoint)), user_text, in_flight.clone()));
+
+    let mut group = c.benchmark_group("forward");
+    group.throughput(Throughput::Elements(MESSAGES));
+    group.bench_function("private connection per message", |b| b.iter(|| {
+        for idx in 0..MESSAGES {
+            forward_private(&account, &rori, discord_msg(idx));
+        }
+    }));
+    group.bench_function("Endpoint::forward_messages", |b| b.iter(|| rt.block_on(async {
+        for idx in 0..MESSAGES {
+            in_flight.fetch_add(1, Ordering::SeqCst);
+            user_say.send(discord_msg(idx)).expect("Endpoint stopped");
+        }
+        while in_flight.load(Ordering::SeqCst) > 0 {
+            let _ = tokio::task::yield_now().await;
+        }
+    })));
+    group.finish();
+}
+
+criterion_group!(benches, forward_messages);
+criterion_main!(benches);
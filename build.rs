@@ -0,0 +1,58 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+extern crate dbus_codegen;
+
+use dbus_codegen::{ConnectionType, GenOpts};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/**
+ * Generate the typed ConfigurationManager proxies from the introspection XML,
+ * for the Ring (cx.ring.Ring) and the Jami (net.jami.daemon) namespaces
+ */
+fn main() {
+    // Next to this script, which is also the build script of fuzz/
+    let introspection = Path::new(file!()).with_file_name("dbus/configurationmanager-introspec.xml");
+    println!("cargo:rerun-if-changed={}", introspection.display());
+    let xml = fs::read_to_string(&introspection).expect("Can't read the introspection XML");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    for (namespace, file) in &[("cx.ring.Ring", "ring_configurationmanager.rs"),
+                               ("net.jami.daemon", "jami_configurationmanager.rs")] {
+        let opts = GenOpts {
+            methodtype: None,
+            skipprefix: Some(format!("{}.", namespace)),
+            connectiontype: ConnectionType::Nonblock,
+            ..Default::default()
+        };
+        let code = dbus_codegen::generate(&xml.replace("cx.ring.Ring", namespace), &opts)
+            .expect("Can't generate the ConfigurationManager proxy");
+        fs::write(out_dir.join(file), code).expect("Can't write the ConfigurationManager proxy");
+    }
+}
@@ -0,0 +1,98 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+/*
+ * Feed arbitrary signals to the decoders of src/rori/signals.rs: they must never panic
+ * Run with: cargo +nightly fuzz run decode_signal
+ */
+
+#![no_main]
+extern crate arbitrary;
+extern crate dbus;
+#[macro_use]
+extern crate libfuzzer_sys;
+
+#[path = "../../src/rori/configurationmanager.rs"]
+mod configurationmanager;
+#[path = "../../src/rori/signals.rs"]
+#[allow(dead_code)]
+mod signals;
+
+use arbitrary::Arbitrary;
+use dbus::Message;
+use std::collections::HashMap;
+
+const IFACE: &str = "cx.ring.Ring.ConfigurationManager";
+
+#[derive(Arbitrary, Debug)]
+enum Arg {
+    Str(String),
+    Int(i32),
+    UInt64(u64),
+    Bool(bool),
+    Bytes(Vec<u8>),
+    Strs(Vec<String>),
+    Dict(HashMap<String, String>),
+    Dicts(Vec<HashMap<String, String>>),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Signal {
+    other_interface: bool,
+    member: u8,
+    args: Vec<Arg>,
+}
+
+// D-Bus strings can't contain a nul byte (append panics)
+fn clean(s: String) -> String {
+    s.replace('\0', "")
+}
+
+fn clean_dict(dict: HashMap<String, String>) -> HashMap<String, String> {
+    dict.into_iter().map(|(key, value)| (clean(key), clean(value))).collect()
+}
+
+fuzz_target!(|signal: Signal| {
+    let iface = if signal.other_interface { "org.example.Other" } else { IFACE };
+    let member = signals::MEMBERS.get(signal.member as usize).cloned().unwrap_or("nameRegistrationEnded");
+    let mut msg = Message::signal(&"/cx/ring/Ring/ConfigurationManager".into(), &iface.into(), &member.into());
+    for arg in signal.args {
+        msg = match arg {
+            Arg::Str(s) => msg.append1(clean(s)),
+            Arg::Int(i) => msg.append1(i),
+            Arg::UInt64(u) => msg.append1(u),
+            Arg::Bool(b) => msg.append1(b),
+            Arg::Bytes(bytes) => msg.append1(bytes),
+            Arg::Strs(strs) => msg.append1(strs.into_iter().map(clean).collect::<Vec<String>>()),
+            Arg::Dict(dict) => msg.append1(clean_dict(dict)),
+            Arg::Dicts(dicts) => msg.append1(dicts.into_iter().map(clean_dict).collect::<Vec<_>>()),
+        };
+    }
+    let _ = signals::decode(&msg, IFACE);
+    let _ = signals::read::<signals::NameRegistrationEnded>(&msg, member);
+});
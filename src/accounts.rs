@@ -0,0 +1,224 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use crate::config::ConfigFile;
+use crate::rori::account::Account;
+use crate::rori::endpoint::Endpoint;
+use crate::setup::{ask, create_account, find_account, list_accounts};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::path::Path;
+
+/**
+ * @return the account subcommand
+ */
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    let account_arg = Arg::with_name("account")
+        .required(true)
+        .value_name("ACCOUNT")
+        .help("Alias, Ring id or account id");
+    SubCommand::with_name("account")
+        .about("Manage the Ring accounts of the daemon")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("list")
+            .about("List accounts with their details"))
+        .subcommand(SubCommand::with_name("add")
+            .about("Create an account, or import it from an archive")
+            .arg(Arg::with_name("alias")
+                .long("alias")
+                .value_name("ALIAS")
+                .required_unless("archive")
+                .conflicts_with("archive")
+                .help("Alias of the new account"))
+            .arg(Arg::with_name("archive")
+                .long("archive")
+                .value_name("PATH")
+                .help("Archive to import"))
+            .arg(Arg::with_name("password")
+                .long("password")
+                .value_name("PASSWORD")
+                .env("RORI_BOT_ACCOUNT_PASSWORD")
+                .hide_env_values(true)
+                .help("Password of the archive")))
+        .subcommand(SubCommand::with_name("enable")
+            .about("Enable an account")
+            .arg(account_arg.clone()))
+        .subcommand(SubCommand::with_name("disable")
+            .about("Disable an account")
+            .arg(account_arg.clone()))
+        .subcommand(SubCommand::with_name("export")
+            .about("Export an account to an archive")
+            .arg(account_arg.clone())
+            .arg(Arg::with_name("path")
+                .required(true)
+                .value_name("PATH")
+                .help("Archive to write"))
+            .arg(Arg::with_name("password")
+                .long("password")
+                .value_name("PASSWORD")
+                .env("RORI_BOT_ACCOUNT_PASSWORD")
+                .hide_env_values(true)
+                .help("Password of the archive")))
+        .subcommand(SubCommand::with_name("remove")
+            .about("Remove an account from the daemon")
+            .arg(account_arg.clone())
+            .arg(Arg::with_name("yes")
+                .short("y")
+                .long("yes")
+                .help("Don't ask for confirmation")))
+        .subcommand(SubCommand::with_name("register-name")
+            .about("Register a public name for an account on its name server")
+            .arg(account_arg.clone())
+            .arg(Arg::with_name("name")
+                .required(true)
+                .value_name("NAME")
+                .help("Name to register"))
+            .arg(Arg::with_name("password")
+                .long("password")
+                .value_name("PASSWORD")
+                .env("RORI_BOT_ACCOUNT_PASSWORD")
+                .hide_env_values(true)
+                .help("Password of the account")))
+        .subcommand(SubCommand::with_name("switch")
+            .about("Use another account in the config file")
+            .arg(account_arg)
+            .arg(Arg::with_name("bridge")
+                .long("bridge")
+                .value_name("ACCOUNT_ID")
+                .help("Account of the bridge to replace, if bridges are configured")))
+}
+
+/**
+ * Run the account subcommand
+ * @param matches of the account subcommand
+ * @param config_path
+ * @return an error to show if any
+ */
+pub async fn run(matches: &ArgMatches<'_>, config_path: &Path) -> Result<(), String> {
+    Endpoint::ping_daemon().await?;
+    match matches.subcommand() {
+        ("list", Some(_)) => {
+            for account in Endpoint::get_account_list().await {
+                let status = Endpoint::get_volatile_details(&account.id).await
+                    .map(|details| details.get("Account.registrationStatus").cloned().unwrap_or_default())
+                    .unwrap_or_default();
+                println!("{} - Status: {}", account, status);
+            }
+            Ok(())
+        },
+        ("add", Some(add)) => {
+            let password = add.value_of("password").unwrap_or("");
+            let account_id = match add.value_of("archive") {
+                Some(path) => create_account(path, password, true).await?,
+                None => create_account(add.value_of("alias").unwrap_or(""), password, false).await?,
+            };
+            println!("{}", account_id);
+            Ok(())
+        },
+        ("enable", Some(enable)) => Endpoint::set_account_enabled(&get_account(enable).await?.id, true).await,
+        ("disable", Some(disable)) => Endpoint::set_account_enabled(&get_account(disable).await?.id, false).await,
+        ("export", Some(export)) => {
+            let account = get_account(export).await?;
+            let path = export.value_of("path").unwrap_or("");
+            Endpoint::export_account(&account.id, path, export.value_of("password").unwrap_or("")).await?;
+            println!("{} exported to {}", account.id, path);
+            Ok(())
+        },
+        ("remove", Some(remove)) => {
+            let account = get_account(remove).await?;
+            if !remove.is_present("yes")
+                && ask(&format!("Remove {}? y/N: ", account)).to_lowercase() != "y" {
+                return Ok(());
+            }
+            Endpoint::remove_account(&account.id).await
+        },
+        ("register-name", Some(register)) => {
+            let account = get_account(register).await?;
+            let name = register.value_of("name").unwrap_or("");
+            register_name(&account.id, register.value_of("password").unwrap_or(""), name, config_path).await
+        },
+        ("switch", Some(switch)) => switch_account(&get_account(switch).await?, switch.value_of("bridge"), config_path),
+        _ => Ok(()),
+    }
+}
+
+/**
+ * @param matches with an account argument
+ * @return the account wanted
+ */
+async fn get_account(matches: &ArgMatches<'_>) -> Result<Account, String> {
+    let wanted = String::from(matches.value_of("account").unwrap_or(""));
+    let accounts = Endpoint::get_account_list().await;
+    match find_account(&accounts, &wanted) {
+        Some(account) => Ok(account.clone()),
+        None => Err(format!("No account {}. Available accounts:\n{}", wanted, list_accounts(&accounts))),
+    }
+}
+
+/**
+ * Register a public name for an account, and record it in the config file if the account is used
+ * @param account_id
+ * @param password of the account
+ * @param name to register
+ * @param config_path
+ * @return an error to show if any
+ */
+pub async fn register_name(account_id: &str, password: &str, name: &str, config_path: &Path) -> Result<(), String> {
+    Endpoint::register_name(account_id, password, name).await?;
+    println!("{} registered for {}", name, account_id);
+    if !config_path.exists() {
+        return Ok(());
+    }
+    let mut config = ConfigFile::read(config_path)?;
+    if config.set_ring_name(account_id, name) {
+        config.save(config_path)?;
+    }
+    Ok(())
+}
+
+/**
+ * Replace the account used in the config file
+ * @param account to use
+ * @param bridge account id of the bridge to replace, if bridges are configured
+ * @param config_path
+ * @return an error to show if any
+ */
+fn switch_account(account: &Account, bridge: Option<&str>, config_path: &Path) -> Result<(), String> {
+    let mut config = ConfigFile::read(config_path)?;
+    if config.bridges.is_empty() {
+        config.ring_id = account.id.clone();
+    } else {
+        let bridge = bridge.ok_or("Bridges are configured. Please choose one with --bridge")?;
+        match config.bridges.iter_mut().find(|b| b.ring_id == bridge) {
+            Some(b) => b.ring_id = account.id.clone(),
+            None => return Err(format!("No bridge for {}", bridge)),
+        }
+    }
+    config.save(config_path)?;
+    println!("{} now uses {}", config_path.display(), account.id);
+    Ok(())
+}
@@ -0,0 +1,568 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use crate::discord::BotOptions;
+use crate::discord::ratelimit::RateLimitsConfig;
+use crate::rori::nameserver::{NameServerConfig, TlsConfig};
+use serde_json::Value;
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/**
+ * Prefix of the environment variables overriding the config file.
+ * e.g. RORI_BOT_DISCORD_SECRET_TOKEN overrides discord_secret_token and
+ * RORI_BOT_DISCORD_SECRET_TOKEN_FILE reads it from a file
+ */
+const ENV_PREFIX: &str = "RORI_BOT_";
+
+/**
+ * Config of the bot, read from a JSON or a TOML file
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub discord_secret_token: String,
+    // Daemon to use: "ring" (cx.ring.Ring), "jami" (net.jami.daemon) or "auto" to detect it
+    #[serde(default = "default_daemon")]
+    pub daemon: String,
+    #[serde(default)]
+    pub ring_id: String,
+    // Public name registered for ring_id
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub ring_name: String,
+    #[serde(default)]
+    pub rori_server: String,
+    #[serde(default)]
+    pub rori_ring_id: String,
+    // Name of the default RORI on rori_server, resolved into rori_ring_id
+    #[serde(default = "default_rori_name")]
+    pub rori_name: String,
+    #[serde(default)]
+    pub paginate_after: usize,
+    #[serde(default = "default_allowed_mentions")]
+    pub allowed_mentions: Vec<String>,
+    #[serde(default = "default_rori_timeout")]
+    pub rori_timeout: u64,
+    #[serde(default = "default_rori_timeout_notice")]
+    pub rori_timeout_notice: String,
+    // Discord user ids allowed to use admin commands
+    #[serde(default)]
+    pub admins: Vec<String>,
+    // Post messages relayed by RORI with webhooks named as their authors
+    #[serde(default)]
+    pub webhooks: bool,
+    // Seconds to forward pending messages on SIGINT/SIGTERM
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    // Show the bot offline before shutting down
+    #[serde(default)]
+    pub offline_on_shutdown: bool,
+    // Address of the local HTTP endpoint (/metrics, /health, /ready), e.g. 127.0.0.1:9898. Empty to disable
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub http_listen: String,
+    #[serde(default = "RateLimitsConfig::new")]
+    pub rate_limits: RateLimitsConfig,
+    // TLS settings for the name servers
+    #[serde(default = "TlsConfig::new")]
+    pub tls: TlsConfig,
+    // Timeouts, retries and cache of the name servers
+    #[serde(default = "NameServerConfig::new")]
+    pub name_server: NameServerConfig,
+    // Tables must be after values in TOML
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<RoriOverride>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bridges: Vec<BridgeConfig>,
+}
+
+/**
+ * A bridge between a Ring account and a RORI, for some guilds
+ * If no bridge is configured, ring_id and rori_ring_id are used for all guilds
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BridgeConfig {
+    pub ring_id: String,
+    // Public name registered for ring_id
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub ring_name: String,
+    pub rori_ring_id: String,
+    // Guild ids. Empty for direct messages and guilds without bridge
+    #[serde(default)]
+    pub guilds: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<RoriOverride>,
+}
+
+/**
+ * Use another RORI (from another name server) for a guild or a channel,
+ * with the same Ring account
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoriOverride {
+    pub guild: String,
+    // Empty for the whole guild
+    #[serde(default)]
+    pub channel: String,
+    pub rori_server: String,
+    #[serde(default = "default_rori_name")]
+    pub rori_name: String,
+}
+
+fn default_daemon() -> String {
+    String::from("auto")
+}
+
+fn default_rori_name() -> String {
+    String::from("rori")
+}
+
+fn default_allowed_mentions() -> Vec<String> {
+    BotOptions::new().allowed_mentions
+}
+
+fn default_rori_timeout() -> u64 {
+    BotOptions::new().rori_timeout
+}
+
+fn default_rori_timeout_notice() -> String {
+    BotOptions::new().rori_timeout_notice
+}
+
+fn default_shutdown_timeout() -> u64 {
+    BotOptions::new().shutdown_timeout
+}
+
+impl ConfigFile {
+    pub fn new() -> ConfigFile {
+        serde_json::from_str("{}").unwrap()
+    }
+
+    /**
+     * Read a config file, then apply the environment variables
+     * @param path to a JSON or TOML (.toml) file
+     * @return the config, or an error to show
+     */
+    pub fn load(path: &Path) -> Result<ConfigFile, String> {
+        ConfigFile::read(path)?.with_env()
+    }
+
+    /**
+     * Read a config file, without the environment variables (e.g. to modify it)
+     * @param path to a JSON or TOML (.toml) file
+     * @return the config, or an error to show
+     */
+    pub fn read(path: &Path) -> Result<ConfigFile, String> {
+        let mut file = File::open(path).map_err(|e| format!("Can't open {}: {}", path.display(), e))?;
+        let mut content = String::new();
+        file.read_to_string(&mut content).map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+        let config: ConfigFile = if ConfigFile::is_toml(path) {
+            toml::from_str(&content).map_err(|e| format!("Incorrect config file {}: {}", path.display(), e))?
+        } else {
+            serde_json::from_str(&content).map_err(|e| format!("Incorrect config file {}: {}", path.display(), e))?
+        };
+        Ok(config)
+    }
+
+    /**
+     * Write the config file
+     * @param self
+     * @param path to a JSON or TOML (.toml) file
+     */
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = if ConfigFile::is_toml(path) {
+            toml::to_string_pretty(self).map_err(|e| e.to_string())?
+        } else {
+            serde_json::to_string_pretty(self).map_err(|e| e.to_string())?
+        };
+        let mut file = File::create(path).map_err(|e| format!("Can't create {}: {}", path.display(), e))?;
+        file.write_all(content.as_bytes()).map_err(|e| format!("Can't write {}: {}", path.display(), e))
+    }
+
+    /**
+     * Override each field by RORI_BOT_<FIELD> or by the content of the file RORI_BOT_<FIELD>_FILE.
+     * Strings are taken as is, other fields are parsed as JSON (lists of strings can also be comma separated)
+     * @param self
+     * @return the new config
+     */
+    pub fn with_env(self) -> Result<ConfigFile, String> {
+        let mut value = serde_json::to_value(&self).map_err(|e| e.to_string())?;
+        if let Value::Object(fields) = &mut value {
+            // Not serialized when empty, but they can be overridden too
+            for key in &["ring_name", "http_listen"] {
+                fields.entry(*key).or_insert_with(|| Value::String(String::new()));
+            }
+            for key in &["overrides", "bridges"] {
+                fields.entry(*key).or_insert_with(|| Value::Array(Vec::new()));
+            }
+            for (key, field) in fields.iter_mut() {
+                let var = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+                let raw = match env::var(&var) {
+                    Ok(raw) => raw,
+                    Err(_) => match env::var(format!("{}_FILE", var)) {
+                        Ok(path) => {
+                            let mut raw = String::new();
+                            File::open(&path).and_then(|mut file| file.read_to_string(&mut raw))
+                                .map_err(|e| format!("Can't read {}_FILE ({}): {}", var, path, e))?;
+                            String::from(raw.trim())
+                        },
+                        Err(_) => continue,
+                    },
+                };
+                *field = match field {
+                    Value::String(_) => Value::String(raw),
+                    Value::Array(_) if !raw.trim_start().starts_with('[') => {
+                        Value::Array(raw.split(',').map(|v| Value::String(String::from(v.trim()))).collect())
+                    },
+                    _ => serde_json::from_str(&raw).map_err(|e| format!("Incorrect {}: {}", var, e))?,
+                };
+            }
+        }
+        serde_json::from_value(value).map_err(|e| format!("Incorrect configuration: {}", e))
+    }
+
+    /**
+     * @param self
+     * @return the options of the Discord bot
+     */
+    pub fn bot_options(&self) -> BotOptions {
+        let mut options = BotOptions::new();
+        options.paginate_after = self.paginate_after;
+        options.allowed_mentions = self.allowed_mentions.clone();
+        options.rori_timeout = self.rori_timeout;
+        options.rori_timeout_notice = self.rori_timeout_notice.clone();
+        options.rate_limits = self.rate_limits.clone();
+        options.admins = self.admins.iter().filter_map(|admin| admin.parse::<u64>().ok()).collect();
+        options.rori_server = self.rori_server.clone();
+        options.webhooks = self.webhooks;
+        options.shutdown_timeout = self.shutdown_timeout;
+        options.offline_on_shutdown = self.offline_on_shutdown;
+        options
+    }
+
+    /**
+     * @param self
+     * @return the configured bridges, or one bridge for all guilds with ring_id and rori_ring_id
+     */
+    pub fn get_bridges(&self) -> Vec<BridgeConfig> {
+        if !self.bridges.is_empty() {
+            return self.bridges.clone();
+        }
+        vec![BridgeConfig {
+            ring_id: self.ring_id.clone(),
+            ring_name: self.ring_name.clone(),
+            rori_ring_id: self.rori_ring_id.clone(),
+            guilds: Vec::new(),
+            overrides: self.overrides.clone(),
+        }]
+    }
+
+    /**
+     * Check the values which can't be checked by the schema
+     * @param self
+     * @return the problems found
+     */
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.discord_secret_token.is_empty() {
+            problems.push(String::from("discord_secret_token is empty"));
+        }
+        if !["auto", "ring", "jami"].contains(&&*self.daemon) {
+            problems.push(format!("daemon: unknown value {}", self.daemon));
+        }
+        for mention in &self.allowed_mentions {
+            if !["users", "roles", "everyone"].contains(&&**mention) {
+                problems.push(format!("allowed_mentions: unknown value {}", mention));
+            }
+        }
+        for (name, bucket) in &[("user", &self.rate_limits.user), ("channel", &self.rate_limits.channel),
+                                ("guild", &self.rate_limits.guild)] {
+            if let Some(bucket) = bucket {
+                if bucket.capacity <= 0. || bucket.per_second <= 0. {
+                    problems.push(format!("rate_limits.{}: capacity and per_second must be positive", name));
+                }
+            }
+        }
+        let mut default_bridges = 0;
+        for bridge in self.get_bridges() {
+            if bridge.ring_id.is_empty() {
+                problems.push(String::from("ring_id is empty"));
+            }
+            if bridge.rori_ring_id.is_empty() {
+                problems.push(format!("{}: rori_ring_id is empty", bridge.ring_id));
+            }
+            if bridge.guilds.is_empty() {
+                default_bridges += 1;
+            }
+            for guild in &bridge.guilds {
+                if guild.parse::<u64>().is_err() {
+                    problems.push(format!("{}: incorrect guild id {}", bridge.ring_id, guild));
+                }
+            }
+            for rori_override in &bridge.overrides {
+                if rori_override.guild.parse::<u64>().is_err() {
+                    problems.push(format!("overrides: incorrect guild id {}", rori_override.guild));
+                }
+                if !rori_override.channel.is_empty() && rori_override.channel.parse::<u64>().is_err() {
+                    problems.push(format!("overrides: incorrect channel id {}", rori_override.channel));
+                }
+                if rori_override.rori_server.is_empty() {
+                    problems.push(format!("overrides: rori_server is empty for {}", rori_override.guild));
+                }
+            }
+        }
+        if !self.tls.ca_file.is_empty() && !Path::new(&*self.tls.ca_file).exists() {
+            problems.push(format!("tls.ca_file: {} not found", self.tls.ca_file));
+        }
+        for admin in &self.admins {
+            if admin.parse::<u64>().is_err() {
+                problems.push(format!("admins: incorrect user id {}", admin));
+            }
+        }
+        if !self.http_listen.is_empty() && self.http_listen.parse::<SocketAddr>().is_err() {
+            problems.push(format!("http_listen: incorrect address {}", self.http_listen));
+        }
+        if default_bridges > 1 {
+            problems.push(String::from("bridges: only one bridge can be without guilds"));
+        }
+        problems
+    }
+
+    /**
+     * Compare with a new configuration
+     * @param self
+     * @param new config
+     * @return (changes which can be applied live, changes which need a restart)
+     */
+    pub fn diff(&self, new: &ConfigFile) -> (Vec<String>, Vec<String>) {
+        let mut live = Vec::new();
+        let mut restart = Vec::new();
+        if self.discord_secret_token != new.discord_secret_token {
+            restart.push(String::from("discord_secret_token"));
+        }
+        if self.daemon != new.daemon {
+            restart.push(String::from("daemon"));
+        }
+        if self.http_listen != new.http_listen {
+            restart.push(String::from("http_listen"));
+        }
+        if self.paginate_after != new.paginate_after {
+            live.push(String::from("paginate_after"));
+        }
+        if self.allowed_mentions != new.allowed_mentions {
+            live.push(String::from("allowed_mentions"));
+        }
+        if self.rori_timeout != new.rori_timeout {
+            live.push(String::from("rori_timeout"));
+        }
+        if self.rori_timeout_notice != new.rori_timeout_notice {
+            live.push(String::from("rori_timeout_notice"));
+        }
+        if self.admins != new.admins {
+            live.push(String::from("admins"));
+        }
+        if self.webhooks != new.webhooks {
+            live.push(String::from("webhooks"));
+        }
+        if self.shutdown_timeout != new.shutdown_timeout {
+            live.push(String::from("shutdown_timeout"));
+        }
+        if self.offline_on_shutdown != new.offline_on_shutdown {
+            live.push(String::from("offline_on_shutdown"));
+        }
+        if self.rori_server != new.rori_server {
+            live.push(String::from("rori_server"));
+        }
+        if self.rate_limits != new.rate_limits {
+            live.push(String::from("rate_limits"));
+        }
+        if self.tls != new.tls {
+            live.push(String::from("tls"));
+        }
+        if self.name_server != new.name_server {
+            live.push(String::from("name_server"));
+        }
+        let bridges = self.get_bridges();
+        let new_bridges = new.get_bridges();
+        for bridge in &bridges {
+            let new_bridge = match new_bridges.iter().find(|b| b.ring_id == bridge.ring_id) {
+                Some(new_bridge) => new_bridge,
+                None => {
+                    restart.push(format!("bridge {} removed", bridge.ring_id));
+                    continue;
+                }
+            };
+            if bridge.rori_ring_id != new_bridge.rori_ring_id {
+                live.push(format!("rori_ring_id of {}", bridge.ring_id));
+            }
+            if bridge.guilds != new_bridge.guilds {
+                live.push(format!("guilds of {}", bridge.ring_id));
+            }
+            if bridge.overrides != new_bridge.overrides {
+                live.push(format!("overrides of {}", bridge.ring_id));
+            }
+        }
+        for new_bridge in &new_bridges {
+            if !bridges.iter().any(|b| b.ring_id == new_bridge.ring_id) {
+                restart.push(format!("bridge {} added", new_bridge.ring_id));
+            }
+        }
+        (live, restart)
+    }
+
+    /**
+     * Record the public name of an account
+     * @param self
+     * @param ring_id account id
+     * @param name registered
+     * @return if the account is used in the config
+     */
+    pub fn set_ring_name(&mut self, ring_id: &str, name: &str) -> bool {
+        let mut found = false;
+        if self.ring_id == ring_id {
+            self.ring_name = String::from(name);
+            found = true;
+        }
+        for bridge in self.bridges.iter_mut().filter(|bridge| bridge.ring_id == ring_id) {
+            bridge.ring_name = String::from(name);
+            found = true;
+        }
+        found
+    }
+
+    fn is_toml(path: &Path) -> bool {
+        path.extension().map(|ext| ext == "toml").unwrap_or(false)
+    }
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discord::ratelimit::BucketConfig;
+
+    fn valid_config() -> ConfigFile {
+        let mut config = ConfigFile::new();
+        config.discord_secret_token = String::from("token");
+        config.ring_id = String::from("ring_id");
+        config.rori_ring_id = String::from("rori_ring_id");
+        config
+    }
+
+    // The environment is shared by the tests, so all variables are tested here
+    #[test]
+    fn override_with_env() {
+        let token_file = env::temp_dir().join(format!("rori_bot_test_token_{}", std::process::id()));
+        std::fs::write(&token_file, "from file\n").unwrap();
+        env::set_var("RORI_BOT_DISCORD_SECRET_TOKEN_FILE", &token_file);
+        env::set_var("RORI_BOT_RORI_TIMEOUT", "42");
+        env::set_var("RORI_BOT_ADMINS", "1, 2,3");
+        env::set_var("RORI_BOT_ALLOWED_MENTIONS", "[\"roles\"]");
+        env::set_var("RORI_BOT_HTTP_LISTEN", "127.0.0.1:9898");
+        env::set_var("RORI_BOT_BRIDGES", "[{\"ring_id\": \"a\", \"rori_ring_id\": \"b\", \"guilds\": [\"1\"]}]");
+        let config = ConfigFile::new().with_env();
+        env::set_var("RORI_BOT_RORI_TIMEOUT", "soon");
+        let incorrect = ConfigFile::new().with_env();
+        for var in &["DISCORD_SECRET_TOKEN_FILE", "RORI_TIMEOUT", "ADMINS", "ALLOWED_MENTIONS", "HTTP_LISTEN", "BRIDGES"] {
+            env::remove_var(format!("RORI_BOT_{}", var));
+        }
+        std::fs::remove_file(&token_file).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.discord_secret_token, "from file");
+        assert_eq!(config.rori_timeout, 42);
+        assert_eq!(config.admins, vec!["1", "2", "3"]);
+        assert_eq!(config.allowed_mentions, vec!["roles"]);
+        assert_eq!(config.http_listen, "127.0.0.1:9898");
+        assert_eq!(config.bridges.len(), 1);
+        assert_eq!(config.bridges[0].guilds, vec!["1"]);
+        assert!(incorrect.unwrap_err().contains("RORI_BOT_RORI_TIMEOUT"));
+    }
+
+    #[test]
+    fn check_valid_config() {
+        assert!(valid_config().check().is_empty());
+    }
+
+    #[test]
+    fn check_incorrect_values() {
+        let mut config = valid_config();
+        config.discord_secret_token = String::new();
+        config.daemon = String::from("sflphone");
+        config.allowed_mentions = vec![String::from("all")];
+        config.admins = vec![String::from("admin")];
+        config.http_listen = String::from("localhost");
+        config.rate_limits.user = Some(BucketConfig { capacity: 0., per_second: 1. });
+        assert_eq!(config.check().len(), 6);
+    }
+
+    #[test]
+    fn check_bridges() {
+        let mut config = valid_config();
+        let bridge = BridgeConfig {
+            ring_id: String::from("a"),
+            ring_name: String::new(),
+            rori_ring_id: String::from("b"),
+            guilds: Vec::new(),
+            overrides: Vec::new(),
+        };
+        config.bridges = vec![bridge.clone(), bridge.clone()];
+        assert_eq!(config.check(), vec!["bridges: only one bridge can be without guilds"]);
+        config.bridges[1].guilds = vec![String::from("guild")];
+        assert_eq!(config.check(), vec!["a: incorrect guild id guild"]);
+    }
+
+    #[test]
+    fn diff_live_and_restart_changes() {
+        let config = valid_config();
+        let mut new = config.clone();
+        assert_eq!(config.diff(&new), (Vec::new(), Vec::new()));
+        new.rori_timeout = 10;
+        new.discord_secret_token = String::from("other");
+        let (live, restart) = config.diff(&new);
+        assert_eq!(live, vec!["rori_timeout"]);
+        assert_eq!(restart, vec!["discord_secret_token"]);
+    }
+
+    #[test]
+    fn diff_bridges() {
+        let config = valid_config();
+        let mut new = config.clone();
+        new.rori_ring_id = String::from("other");
+        assert_eq!(config.diff(&new).0, vec!["rori_ring_id of ring_id"]);
+        new.ring_id = String::from("other");
+        assert!(config.diff(&new).1.contains(&String::from("bridge ring_id removed")));
+    }
+}
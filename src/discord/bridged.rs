@@ -0,0 +1,173 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use serenity::model::id::{ChannelId, MessageId};
+use std::collections::{HashMap, VecDeque};
+
+/**
+ * Max number of bridged messages remembered
+ */
+const MAX_BRIDGED_MESSAGES: usize = 1000;
+
+/**
+ * Remember messages which went through the bridge (user messages forwarded
+ * to RORI and replies posted by RORI), so later events on them can be forwarded.
+ **/
+#[derive(Debug)]
+pub struct BridgedMessages {
+    // Discord message id -> RORI reply id (empty for user messages)
+    messages: HashMap<u64, String>,
+    // RORI reply id -> message posted on Discord
+    replies: HashMap<String, (ChannelId, MessageId)>,
+    order: VecDeque<u64>,
+}
+
+impl BridgedMessages {
+    pub fn new() -> BridgedMessages {
+        BridgedMessages {
+            messages: HashMap::new(),
+            replies: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /**
+     * Remember a message forwarded to RORI
+     * @param self
+     * @param message_id
+     */
+    pub fn add_message(&mut self, message_id: MessageId) {
+        self.insert(*message_id.as_u64(), String::new());
+    }
+
+    /**
+     * Remember a reply posted by RORI
+     * @param self
+     * @param rid the reply id
+     * @param channel_id where the reply was posted
+     * @param message_id of the Discord message
+     */
+    pub fn add_reply(&mut self, rid: &str, channel_id: ChannelId, message_id: MessageId) {
+        if rid.is_empty() {
+            return;
+        }
+        self.replies.insert(String::from(rid), (channel_id, message_id));
+        self.insert(*message_id.as_u64(), String::from(rid));
+    }
+
+    /**
+     * Forget a reply (deleted for example)
+     * @param self
+     * @param rid the reply id
+     */
+    pub fn remove_reply(&mut self, rid: &str) {
+        if let Some((_, message_id)) = self.replies.remove(rid) {
+            self.messages.remove(message_id.as_u64());
+            self.order.retain(|id| id != message_id.as_u64());
+        }
+    }
+
+    /**
+     * @param self
+     * @param rid the reply id
+     * @return the channel and id of the Discord message if found
+     */
+    pub fn get_reply(&self, rid: &str) -> Option<(ChannelId, MessageId)> {
+        self.replies.get(rid).cloned()
+    }
+
+    /**
+     * @param self
+     * @param message_id
+     * @return None if the message was not bridged, else the RORI reply id (empty for user messages)
+     */
+    pub fn get_message(&self, message_id: MessageId) -> Option<String> {
+        self.messages.get(message_id.as_u64()).cloned()
+    }
+
+    fn insert(&mut self, message_id: u64, rid: String) {
+        if self.messages.insert(message_id, rid).is_none() {
+            self.order.push_back(message_id);
+        }
+        while self.order.len() > MAX_BRIDGED_MESSAGES {
+            if let Some(old) = self.order.pop_front() {
+                if let Some(rid) = self.messages.remove(&old) {
+                    self.replies.remove(&rid);
+                }
+            }
+        }
+    }
+}
+
+impl Default for BridgedMessages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remember_and_forget_replies() {
+        let mut bridged = BridgedMessages::new();
+        bridged.add_reply("r1", ChannelId::from(10), MessageId::from(1));
+        bridged.add_reply("", ChannelId::from(10), MessageId::from(2));
+        assert_eq!(bridged.get_reply("r1"), Some((ChannelId::from(10), MessageId::from(1))));
+        assert_eq!(bridged.get_message(MessageId::from(1)), Some(String::from("r1")));
+        assert_eq!(bridged.get_message(MessageId::from(2)), None);
+        bridged.remove_reply("r1");
+        assert_eq!(bridged.get_reply("r1"), None);
+        assert_eq!(bridged.get_message(MessageId::from(1)), None);
+    }
+
+    #[test]
+    fn oldest_messages_are_evicted() {
+        let mut bridged = BridgedMessages::new();
+        bridged.add_reply("r1", ChannelId::from(10), MessageId::from(1));
+        for id in 2..MAX_BRIDGED_MESSAGES as u64 + 2 {
+            bridged.add_message(MessageId::from(id));
+        }
+        assert_eq!(bridged.get_message(MessageId::from(1)), None);
+        assert_eq!(bridged.get_reply("r1"), None);
+        assert_eq!(bridged.get_message(MessageId::from(2)), Some(String::new()));
+        assert_eq!(bridged.get_message(MessageId::from(MAX_BRIDGED_MESSAGES as u64 + 1)), Some(String::new()));
+    }
+
+    #[test]
+    fn removed_replies_are_not_counted() {
+        let mut bridged = BridgedMessages::new();
+        bridged.add_reply("r1", ChannelId::from(10), MessageId::from(1));
+        bridged.remove_reply("r1");
+        for id in 2..MAX_BRIDGED_MESSAGES as u64 + 2 {
+            bridged.add_message(MessageId::from(id));
+        }
+        assert_eq!(bridged.get_message(MessageId::from(2)), Some(String::new()));
+    }
+}
@@ -0,0 +1,263 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+/**
+ * Max length of a Discord message
+ */
+pub const MESSAGE_MAX_LEN: usize = 2000;
+/**
+ * Max length of an embed description
+ */
+pub const EMBED_MAX_LEN: usize = 2048;
+
+const FENCE: &str = "```";
+
+/**
+ * Split a text in chunks of max_len characters. Cut on paragraphs, then on lines
+ * and only cut inside a line if it's too long. Code blocks are closed at the end of
+ * a chunk and reopened (with the same language) at the start of the next one.
+ * A text short enough is returned unchanged.
+ * @param text to split
+ * @param max_len of a chunk
+ * @return the chunks
+ */
+pub fn split_message(text: &str, max_len: usize) -> Vec<String> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    if len(text) <= max_len {
+        // Fits in one chunk, keep it as RORI sent it
+        return vec![text.to_string()];
+    }
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for block in split_blocks(text) {
+        let pieces = if len(&block) > max_len {
+            split_block(&block, max_len)
+        } else {
+            vec![block]
+        };
+        for piece in pieces {
+            if current.is_empty() {
+                current = piece;
+            } else if len(&current) + 2 + len(&piece) <= max_len {
+                current += "\n\n";
+                current += &*piece;
+            } else {
+                chunks.push(current);
+                current = piece;
+            }
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn len(text: &str) -> usize {
+    text.chars().count()
+}
+
+/**
+ * @param line
+ * @return if the line opens or closes a code block
+ */
+fn is_fence(line: &str) -> bool {
+    line.trim_start().starts_with(FENCE) && line.matches(FENCE).count() % 2 == 1
+}
+
+/**
+ * Split a text in paragraphs. Code blocks are never split.
+ * @param text
+ * @return paragraphs
+ */
+fn split_blocks(text: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+    for line in text.lines() {
+        if !in_fence && line.trim().is_empty() {
+            if !current.is_empty() {
+                blocks.push(current.join("\n"));
+                current.clear();
+            }
+            continue;
+        }
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+        current.push(line);
+    }
+    if !current.is_empty() {
+        blocks.push(current.join("\n"));
+    }
+    blocks
+}
+
+/**
+ * Split a paragraph too long for one chunk, line by line.
+ * @param block
+ * @param max_len
+ * @return pieces of max_len characters
+ */
+fn split_block(block: &str, max_len: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    // Opening line of the current code block
+    let mut fence: Option<String> = None;
+    for line in block.lines() {
+        let reserved = match &fence {
+            Some(open) => len(open) + 1 + FENCE.len() + 1,
+            None => FENCE.len() + 1,
+        };
+        for part in split_line(line, max_len.saturating_sub(reserved).max(1)) {
+            // Always keep room to close a code block
+            if !current.is_empty() && len(&current) + 1 + len(&part) + FENCE.len() + 1 > max_len {
+                if fence.is_some() {
+                    current += "\n";
+                    current += FENCE;
+                }
+                pieces.push(current);
+                current = match &fence {
+                    Some(open) => open.clone(),
+                    None => String::new(),
+                };
+            }
+            if !current.is_empty() {
+                current.push('\n');
+            }
+            current += &*part;
+        }
+        if is_fence(line) {
+            fence = match fence {
+                Some(_) => None,
+                None => Some(String::from(line.trim())),
+            };
+        }
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/**
+ * Split a line too long, on whitespaces if possible
+ * @param line
+ * @param max_len
+ * @return parts of max_len characters
+ */
+fn split_line(line: &str, max_len: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest: Vec<char> = line.chars().collect();
+    while rest.len() > max_len {
+        let cut = match rest[..max_len].iter().rposition(|c| c.is_whitespace()) {
+            Some(0) | None => max_len,
+            Some(pos) => pos,
+        };
+        parts.push(rest[..cut].iter().collect::<String>().trim_end().to_string());
+        rest = rest[cut..].iter().cloned().skip_while(|c| c.is_whitespace()).collect();
+    }
+    parts.push(rest.into_iter().collect());
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_message_is_one_chunk() {
+        assert_eq!(split_message("hello\n\nworld", 100), vec!["hello\n\nworld"]);
+    }
+
+    #[test]
+    fn short_message_is_unchanged() {
+        let text = "  indented\n\n\n\ttabs and trailing spaces  \n\n  \nlast\n";
+        assert_eq!(split_message(text, 100), vec![text]);
+        assert!(split_message(" \n\n ", 100).is_empty());
+    }
+
+    #[test]
+    fn paragraphs_round_trip() {
+        let paragraphs: Vec<String> = (0..30).map(|i| format!("paragraph {} has some words", i)).collect();
+        let text = paragraphs.join("\n\n");
+        let chunks = split_message(&text, 100);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(len(chunk) <= 100, "{:?} is too long", chunk);
+        }
+        assert_eq!(chunks.join("\n\n"), text);
+    }
+
+    #[test]
+    fn cut_on_paragraphs() {
+        let chunks = split_message("first paragraph\n\nsecond paragraph", 20);
+        assert_eq!(chunks, vec!["first paragraph", "second paragraph"]);
+    }
+
+    #[test]
+    fn long_word_is_cut() {
+        let word = "a".repeat(25);
+        let chunks = split_message(&word, 10);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(len(chunk) <= 10, "{:?} is too long", chunk);
+        }
+        assert_eq!(chunks.concat().replace('\n', ""), word);
+    }
+
+    #[test]
+    fn long_line_is_cut_on_whitespaces() {
+        let chunks = split_message("one two three four five six", 12);
+        for chunk in &chunks {
+            assert!(len(chunk) <= 12, "{:?} is too long", chunk);
+        }
+        let words: Vec<&str> = chunks.iter().flat_map(|c| c.split_whitespace()).collect();
+        assert_eq!(words, vec!["one", "two", "three", "four", "five", "six"]);
+    }
+
+    #[test]
+    fn code_blocks_are_reopened() {
+        let lines: Vec<String> = (0..20).map(|i| format!("let x{} = {};", i, i)).collect();
+        let text = format!("```rust\n{}\n```", lines.join("\n"));
+        let chunks = split_message(&text, 60);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(len(chunk) <= 60, "{:?} is too long", chunk);
+            assert!(chunk.starts_with("```rust\n"), "{:?} doesn't reopen the block", chunk);
+            assert!(chunk.ends_with("\n```"), "{:?} doesn't close the block", chunk);
+        }
+        let code: Vec<&str> = chunks.iter()
+            .flat_map(|c| c.lines())
+            .filter(|l| !l.starts_with(FENCE))
+            .collect();
+        assert_eq!(code, lines);
+    }
+}
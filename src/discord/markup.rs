@@ -0,0 +1,180 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use std::collections::HashMap;
+
+/**
+ * Discord markups which can be resolved into readable names
+ **/
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Markup {
+    User(u64),
+    Role(u64),
+    Channel(u64),
+    Emoji { name: String, id: u64 },
+}
+
+/**
+ * Find Discord markups (<@id>, <@!id>, <@&id>, <#id>, <:name:id>, <a:name:id>) in a text
+ * @param text
+ * @return (start, end, markup) with byte positions of the markups
+ */
+pub fn find_markups(text: &str) -> Vec<(usize, usize, Markup)> {
+    let mut markups = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = text[pos..].find('<') {
+        let start = pos + start;
+        let end = match text[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        match parse_markup(&text[start + 1..end]) {
+            Some(markup) => {
+                markups.push((start, end + 1, markup));
+                pos = end + 1;
+            },
+            None => pos = start + 1,
+        }
+    }
+    markups
+}
+
+fn parse_markup(inner: &str) -> Option<Markup> {
+    let parse_id = |id: &str| -> Option<u64> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        id.parse::<u64>().ok()
+    };
+    if let Some(id) = inner.strip_prefix("@&") {
+        return Some(Markup::Role(parse_id(id)?));
+    }
+    if let Some(id) = inner.strip_prefix("@!") {
+        return Some(Markup::User(parse_id(id)?));
+    }
+    if let Some(id) = inner.strip_prefix('@') {
+        return Some(Markup::User(parse_id(id)?));
+    }
+    if let Some(id) = inner.strip_prefix('#') {
+        return Some(Markup::Channel(parse_id(id)?));
+    }
+    let emoji = inner.strip_prefix("a:").or_else(|| inner.strip_prefix(':'))?;
+    let mut parts = emoji.splitn(2, ':');
+    let name = parts.next()?;
+    let id = parse_id(parts.next()?)?;
+    if name.is_empty() {
+        return None;
+    }
+    Some(Markup::Emoji { name: String::from(name), id })
+}
+
+/**
+ * Replace Discord markups by readable names
+ * @param text
+ * @param names resolved names for the markups. Emojis are always replaced by :name:
+ * @return the readable text
+ */
+pub fn replace_markups(text: &str, names: &HashMap<Markup, String>) -> String {
+    let mut result = String::new();
+    let mut pos = 0;
+    for (start, end, markup) in find_markups(text) {
+        let name = match &markup {
+            Markup::Emoji { name, .. } => Some(format!(":{}:", name)),
+            _ => names.get(&markup).cloned(),
+        };
+        if let Some(name) = name {
+            result += &text[pos..start];
+            result += &*name;
+            pos = end;
+        }
+    }
+    result += &text[pos..];
+    result
+}
+
+/**
+ * Neutralize @everyone and @here. Users and roles pings are controlled by allowed mentions
+ * @param text
+ * @param allow_everyone if @everyone and @here can be used
+ * @return the escaped text
+ */
+pub fn escape_mentions(text: &str, allow_everyone: bool) -> String {
+    if allow_everyone {
+        return String::from(text);
+    }
+    // A zero width space after @ avoids the ping
+    text.replace("@everyone", "@\u{200B}everyone")
+        .replace("@here", "@\u{200B}here")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_all_markups() {
+        let text = "<@1> <@!2> <@&3> <#4> <:smile:5> <a:dance:6>";
+        let markups: Vec<Markup> = find_markups(text).into_iter().map(|(_, _, m)| m).collect();
+        assert_eq!(markups, vec![
+            Markup::User(1),
+            Markup::User(2),
+            Markup::Role(3),
+            Markup::Channel(4),
+            Markup::Emoji { name: String::from("smile"), id: 5 },
+            Markup::Emoji { name: String::from("dance"), id: 6 },
+        ]);
+    }
+
+    #[test]
+    fn find_markups_positions() {
+        assert_eq!(find_markups("hi <@42>!"), vec![(3, 8, Markup::User(42))]);
+    }
+
+    #[test]
+    fn ignore_invalid_markups() {
+        assert!(find_markups("a < b > c <@abc> <#> <::1> <@12").is_empty());
+        // A stray < doesn't hide the next markup
+        assert_eq!(find_markups("1 < 2 <@3>"), vec![(6, 10, Markup::User(3))]);
+    }
+
+    #[test]
+    fn replace_resolved_markups() {
+        let mut names = HashMap::new();
+        names.insert(Markup::User(1), String::from("@alice"));
+        names.insert(Markup::Channel(2), String::from("#general"));
+        let text = replace_markups("<@!1> in <#2> with <@&3> <:smile:4>", &names);
+        assert_eq!(text, "@alice in #general with <@&3> :smile:");
+    }
+
+    #[test]
+    fn escape_everyone_and_here() {
+        assert_eq!(escape_mentions("@everyone @here <@1>", false),
+                   "@\u{200B}everyone @\u{200B}here <@1>");
+        assert_eq!(escape_mentions("@everyone @here", true), "@everyone @here");
+    }
+}
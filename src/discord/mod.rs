@@ -1,4 +1,4 @@
-/**
+/*
  * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
  * All rights reserved.
  * Redistribution and use in source and binary forms, with or without
@@ -25,15 +25,42 @@
  * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  **/
 
+pub mod bridged;
+pub mod chunker;
+pub mod markup;
+pub mod paginator;
+pub mod pending;
+pub mod ratelimit;
+
+use self::bridged::BridgedMessages;
+use self::chunker::{split_message, EMBED_MAX_LEN, MESSAGE_MAX_LEN};
+use self::markup::{escape_mentions, find_markups, replace_markups, Markup};
+use self::paginator::{Paginator, NEXT_PAGE, PREVIOUS_PAGE};
+use self::pending::PendingRequests;
+use self::ratelimit::{RateLimiter, RateLimitsConfig, Throttle};
+use crate::health;
+use crate::metrics;
+use crate::rori::nameserver::NameServer;
 use serenity::async_trait;
+use serenity::builder::{CreateAllowedMentions, ParseValue};
+use serenity::client::bridge::gateway::ShardManager;
 use serenity::http::Http;
-use serenity::model::channel::Message;
-use serenity::model::id::ChannelId;
+use serenity::model::channel::{Message, Reaction, ReactionType};
+use serenity::model::event::MessageUpdateEvent;
+use serenity::model::id::{ChannelId, EmojiId, GuildId, MessageId, UserId};
 use serenity::model::gateway::Ready;
+use serenity::model::user::{OnlineStatus, User};
+use serenity::model::webhook::Webhook;
 use serenity::prelude::*;
-use serenity::utils::MessageBuilder;
+use serenity::utils::{hashmap_to_json_map, MessageBuilder};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{Instant, Interval};
 
 /**
  * Represent a RING account, just here to store informations.
@@ -41,18 +68,93 @@ use std::sync::mpsc;
 #[derive(Debug)]
 pub struct Bot {
     ready: Option<Ready>,
-    ready_rcv: Option<mpsc::Receiver<Ready>>,
+    ready_rcv: Option<Mutex<mpsc::Receiver<Ready>>>,
     secret_token: String,
+    options: BotOptions,
+    bridged: Arc<Mutex<BridgedMessages>>,
+    paginator: Arc<Mutex<Paginator>>,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+    pending: Arc<Mutex<PendingRequests>>,
+    routes: Arc<Mutex<Vec<Route>>>,
+    // Channel id -> guild id (None for direct messages)
+    channels: Arc<Mutex<HashMap<u64, Option<u64>>>>,
+    admins: Arc<Mutex<Vec<u64>>>,
+    // What the RORIs say, with the name of their route
+    rori_said: UnboundedReceiver<(String, DiscordMsg)>,
+    // Reloads of the configuration, asked in a channel by an admin or by SIGHUP (None)
+    reload_requests: UnboundedReceiver<Option<ChannelId>>,
+    reload_sender: UnboundedSender<Option<ChannelId>>,
+    // When to refresh the typing indicators and to expire pending requests
+    pending_check: Interval,
+    // To show the names of the Ring users relayed by RORI
+    name_server: NameServer,
+    // Webhooks used to post as relayed users
+    webhooks: HashMap<ChannelId, Webhook>,
+    // False once shutting down: new Discord messages are not forwarded
+    accepting: Arc<AtomicBool>,
+}
+
+/**
+ * Options of the bot, from the config file
+ **/
+#[derive(Debug, Clone)]
+pub struct BotOptions {
+    // Post replies longer than this number of messages as a paginated embed. 0 to disable
+    pub paginate_after: usize,
+    // Mentions RORI can trigger: "users", "roles" and/or "everyone"
+    pub allowed_mentions: Vec<String>,
+    pub rate_limits: RateLimitsConfig,
+    // Seconds to wait for an answer from RORI
+    pub rori_timeout: u64,
+    // Posted when RORI doesn't answer in time. Empty to disable
+    pub rori_timeout_notice: String,
+    // Discord users allowed to use admin commands (e.g. /reload)
+    pub admins: Vec<u64>,
+    // Name server used to resolve the Ring ids of relayed users
+    pub rori_server: String,
+    // Post messages relayed by RORI with a webhook named as the author. Else the author prefixes the message
+    pub webhooks: bool,
+    // Seconds to forward pending messages when shutting down
+    pub shutdown_timeout: u64,
+    // Show the bot offline before shutting down
+    pub offline_on_shutdown: bool,
+}
+
+impl BotOptions {
+    pub fn new() -> BotOptions {
+        BotOptions {
+            paginate_after: 0,
+            allowed_mentions: vec![String::from("users")],
+            rate_limits: RateLimitsConfig::new(),
+            rori_timeout: 60,
+            rori_timeout_notice: String::from("RORI didn't answer, please try again later."),
+            admins: Vec::new(),
+            rori_server: String::new(),
+            webhooks: false,
+            shutdown_timeout: 10,
+            offline_on_shutdown: false,
+        }
+    }
+}
+
+impl Default for BotOptions {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /**
  * Represent a Discord message. Is converted into/from an Interaction
+ * NOTE: for edits and deletions, id is the id of the original message
  **/
+#[derive(Debug)]
 pub struct DiscordMsg {
     pub id: String,
     pub body: String,
     pub author: String,
-    pub channel: String
+    pub channel: String,
+    pub datatype: String,
+    pub metadatas: HashMap<String, String>,
 }
 
 impl DiscordMsg {
@@ -62,10 +164,18 @@ impl DiscordMsg {
             body: String::new(),
             author: String::new(),
             channel: String::new(),
+            datatype: String::from("text/plain"),
+            metadatas: HashMap::new(),
         }
     }
 }
 
+impl Default for DiscordMsg {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Clone for DiscordMsg {
     fn clone(&self) -> DiscordMsg {
         DiscordMsg {
@@ -73,23 +183,326 @@ impl Clone for DiscordMsg {
             body: self.body.clone(),
             author: self.author.clone(),
             channel: self.channel.clone(),
+            datatype: self.datatype.clone(),
+            metadatas: self.metadatas.clone(),
         }
     }
 }
 
+/**
+ * Messages from Discord, sent by the Bot to the Endpoint of a route
+ */
+pub type UserSay = UnboundedSender<DiscordMsg>;
+
+/**
+ * What RORI says, sent by the Endpoints to the Bot with the name of their route
+ */
+pub type RoriSay = UnboundedSender<(String, DiscordMsg)>;
+
+/**
+ * A bridge to a RORI. Messages from its guilds are sent to user_say, and what
+ * its RORI says can only be posted in its guilds.
+ **/
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub name: String,
+    // Empty for the default route (direct messages and guilds without route)
+    pub guilds: Vec<u64>,
+    pub user_say: UserSay,
+    // Messages sent to user_say and not forwarded to RORI yet
+    pub in_flight: Arc<AtomicUsize>,
+}
+
+/**
+ * What the Bot has to do next, see Bot::next_event
+ */
+#[derive(Debug)]
+pub enum BotEvent {
+    // A message from the RORI of a route
+    FromRori(String, DiscordMsg),
+    // Reload the configuration, asked in a channel or by SIGHUP (None)
+    Reload(Option<ChannelId>),
+    // Refresh the typing indicators and expire pending requests
+    CheckPending,
+}
+
+impl Route {
+    /**
+     * Find the route of a guild
+     * @param routes
+     * @param guild None for direct messages
+     * @return the index of the route if any
+     */
+    pub fn find(routes: &[Route], guild: Option<u64>) -> Option<usize> {
+        if let Some(guild) = guild {
+            if let Some(idx) = routes.iter().position(|route| route.guilds.contains(&guild)) {
+                return Some(idx);
+            }
+        }
+        routes.iter().position(|route| route.guilds.is_empty())
+    }
+}
+
 /**
  * Shared informations between the Bot and the handler
  */
 struct Handler {
-    user_say: Arc<Mutex<DiscordMsg>>,
+    routes: Arc<Mutex<Vec<Route>>>,
+    channels: Arc<Mutex<HashMap<u64, Option<u64>>>>,
     sender: Arc<Mutex<mpsc::Sender<Ready>>>,
-    current_user: Arc<Mutex<u64>>
+    current_user: Arc<Mutex<u64>>,
+    bridged: Arc<Mutex<BridgedMessages>>,
+    paginator: Arc<Mutex<Paginator>>,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+    pending: Arc<Mutex<PendingRequests>>,
+    admins: Arc<Mutex<Vec<u64>>>,
+    reload_requests: UnboundedSender<Option<ChannelId>>,
+    accepting: Arc<AtomicBool>,
+}
+
+impl Handler {
+    /**
+     * Get the queue of the RORI handling a channel
+     * @param self
+     * @param guild_id of the event if known
+     * @param channel_id of the event
+     * @return the route and the guild if a route exists and the bot is not shutting down
+     */
+    fn user_say(&self, guild_id: Option<GuildId>, channel_id: ChannelId) -> Option<(Route, Option<u64>)> {
+        if !self.accepting.load(Ordering::SeqCst) {
+            return None;
+        }
+        let guild = match guild_id {
+            Some(guild_id) => Some(*guild_id.as_u64()),
+            None => self.channels.lock().unwrap().get(channel_id.as_u64()).cloned().unwrap_or(None),
+        };
+        let routes = self.routes.lock().unwrap();
+        let idx = Route::find(&routes, guild)?;
+        Some((routes[idx].clone(), guild))
+    }
+
+    /**
+     * Send a message to RORI, with the guild ("gd" metadata) if any
+     * @param user_say the route and the guild from user_say()
+     * @param msg
+     */
+    fn push(user_say: (Route, Option<u64>), mut msg: DiscordMsg) {
+        let (route, guild) = user_say;
+        if let Some(guild) = guild {
+            msg.metadatas.insert(String::from("gd"), guild.to_string());
+        }
+        // Counted before sending, the Endpoint may forward it right away
+        route.in_flight.fetch_add(1, Ordering::SeqCst);
+        metrics::QUEUE_DEPTH.with_label_values(&[&*route.name, "to_rori"]).inc();
+        if route.user_say.send(msg).is_err() {
+            route.in_flight.fetch_sub(1, Ordering::SeqCst);
+            metrics::QUEUE_DEPTH.with_label_values(&[&*route.name, "to_rori"]).dec();
+            error!("The bridge {} is stopped, message dropped", route.name);
+            return;
+        }
+        metrics::MESSAGES.with_label_values(&["to_rori", &*metrics::guild_label(Some(guild))]).inc();
+    }
+
+    /**
+     * Take a token in the user, channel and guild buckets before forwarding an event
+     * @param self
+     * @param user author of the event
+     * @param channel of the event
+     * @param guild of the event if any
+     * @return the limit reached and if the user should be notified, None if the event can be forwarded
+     */
+    fn throttle(&self, user: u64, channel: u64, guild: Option<u64>) -> Option<(Throttle, bool)> {
+        match self.rate_limiter.lock().unwrap().try_acquire(user, channel, guild) {
+            Ok(()) => {
+                metrics::RATE_LIMIT.with_label_values(&["forwarded"]).inc();
+                None
+            },
+            Err((kind, notify)) => {
+                metrics::RATE_LIMIT.with_label_values(&[&*format!("throttled_{}", kind)]).inc();
+                Some((kind, notify))
+            },
+        }
+    }
+
+    /**
+     * Tell the user a message was not forwarded to RORI
+     * @param self
+     * @param ctx
+     * @param msg throttled
+     * @param kind of limit reached
+     * @param notify if the user should be notified
+     */
+    async fn notify_throttled(&self, ctx: &Context, msg: &Message, kind: Throttle, notify: bool) {
+        let (config, stats) = {
+            let rate_limiter = self.rate_limiter.lock().unwrap();
+            (rate_limiter.config().clone(), rate_limiter.stats())
+        };
+        info!("Message {} throttled ({} limit). {}", msg.id, kind, stats);
+        if !notify {
+            return;
+        }
+        if !config.reaction.is_empty() {
+            if let Err(why) = msg.channel_id.create_reaction(&ctx.http, msg.id,
+                                                             ReactionType::Unicode(config.reaction.clone())).await {
+                error!("Error adding reaction: {:?}", why);
+                metrics::DISCORD_ERRORS.with_label_values(&["react"]).inc();
+            }
+        }
+        if !config.notice.is_empty() {
+            if let Err(why) = msg.channel_id.say(&ctx.http, &config.notice).await {
+                error!("Error sending message: {:?}", why);
+                metrics::DISCORD_ERRORS.with_label_values(&["send"]).inc();
+            }
+        }
+    }
+
+    /**
+     * Resolve Discord markups (mentions, channels, custom emojis) into readable names
+     * @param self
+     * @param ctx
+     * @param text to resolve
+     * @param mentions users mentioned in the message
+     * @param guild_id of the message if any
+     * @return the readable text and metadatas with the ids of the mentions
+     */
+    async fn resolve_markups(&self, ctx: &Context, text: &str, mentions: &[User],
+                             guild_id: Option<GuildId>) -> (String, HashMap<String, String>) {
+        let mut names: HashMap<Markup, String> = HashMap::new();
+        let mut ids: HashMap<&str, Vec<String>> = HashMap::new();
+        let mut seen: HashSet<Markup> = HashSet::new();
+        let mut roles = None;
+        for (_, _, markup) in find_markups(text) {
+            if !seen.insert(markup.clone()) {
+                continue;
+            }
+            let (key, id, name) = match &markup {
+                Markup::User(id) => {
+                    let name = match mentions.iter().find(|u| *u.id.as_u64() == *id) {
+                        Some(user) => Some(user.name.clone()),
+                        None => UserId::from(*id).to_user(ctx).await.ok().map(|u| u.name),
+                    };
+                    ("mentioned_users", *id, name.map(|name| format!("@{}", name)))
+                },
+                Markup::Role(id) => {
+                    if roles.is_none() {
+                        roles = match guild_id {
+                            Some(guild_id) => Some(ctx.http.get_guild_roles(*guild_id.as_u64()).await.unwrap_or_default()),
+                            None => Some(Vec::new()),
+                        };
+                    }
+                    let name = roles.as_ref().unwrap().iter()
+                        .find(|role| *role.id.as_u64() == *id)
+                        .map(|role| format!("@{}", role.name));
+                    ("mentioned_roles", *id, name)
+                },
+                Markup::Channel(id) => {
+                    let name = match ChannelId::from(*id).to_channel(ctx).await {
+                        Ok(channel) => channel.guild().map(|channel| format!("#{}", channel.name)),
+                        Err(_) => None,
+                    };
+                    ("mentioned_channels", *id, name)
+                },
+                Markup::Emoji { id, .. } => ("emojis", *id, None),
+            };
+            ids.entry(key).or_default().push(id.to_string());
+            if let Some(name) = name {
+                names.insert(markup, name);
+            }
+        }
+        let metadatas = ids.into_iter()
+            .map(|(key, ids)| (String::from(key), ids.join(",")))
+            .collect();
+        (replace_markups(text, &names), metadatas)
+    }
+
+    /**
+     * Browse a paginated reply
+     * @param self
+     * @param ctx
+     * @param reaction
+     * @return true if the reaction was a page control on a paginated reply
+     */
+    async fn turn_page(&self, ctx: &Context, reaction: &Reaction) -> bool {
+        let message_id = *reaction.message_id.as_u64();
+        let emoji = reaction.emoji.to_string();
+        if !Paginator::is_control(&emoji) || !self.paginator.lock().unwrap().contains(message_id) {
+            return false;
+        }
+        let user_id = match reaction.user_id {
+            Some(user_id) => user_id,
+            None => return true,
+        };
+        if *user_id.as_u64() == *self.current_user.lock().unwrap() {
+            // Controls added by the bot
+            return true;
+        }
+        let page = self.paginator.lock().unwrap().turn(message_id, &emoji);
+        if let Some((page, current, total)) = page {
+            let footer = format!("{}/{}", current + 1, total);
+            if let Err(why) = reaction.channel_id.edit_message(&ctx.http, reaction.message_id,
+                |m| m.embed(|e| e.description(page).footer(|f| f.text(footer)))).await {
+                error!("Error editing page: {:?}", why);
+                metrics::DISCORD_ERRORS.with_label_values(&["edit"]).inc();
+            }
+        }
+        // Let the user click again
+        if let Err(why) = reaction.channel_id.delete_reaction(&ctx.http, reaction.message_id,
+                                                              Some(user_id), reaction.emoji.clone()).await {
+            debug!("Can't remove reaction: {:?}", why);
+        }
+        true
+    }
+
+    /**
+     * Forward a reaction on a bridged message to RORI
+     * @param self
+     * @param reaction
+     * @param datatype discord/reaction-add or discord/reaction-remove
+     */
+    fn forward_reaction(&self, reaction: Reaction, datatype: &str) {
+        let user_id = match reaction.user_id {
+            Some(user_id) => *user_id.as_u64(),
+            None => return,
+        };
+        if user_id == *self.current_user.lock().unwrap() {
+            return;
+        }
+        let rid = match self.bridged.lock().unwrap().get_message(reaction.message_id) {
+            Some(rid) => rid,
+            None => return,
+        };
+        let user_say = match self.user_say(reaction.guild_id, reaction.channel_id) {
+            Some(user_say) => user_say,
+            None => return,
+        };
+        let guild_id = reaction.guild_id.map(|guild_id| *guild_id.as_u64());
+        if let Some((kind, _)) = self.throttle(user_id, *reaction.channel_id.as_u64(), guild_id) {
+            info!("Reaction on {} throttled ({} limit)", reaction.message_id, kind);
+            return;
+        }
+        let mut metadatas = HashMap::new();
+        if !rid.is_empty() {
+            metadatas.insert(String::from("rid"), rid);
+        }
+        Handler::push(user_say, DiscordMsg {
+            id: reaction.message_id.as_u64().to_string(),
+            body: reaction.emoji.to_string(),
+            author: user_id.to_string(),
+            channel: reaction.channel_id.as_u64().to_string(),
+            datatype: String::from(datatype),
+            metadatas,
+        });
+    }
 }
 
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
+        if msg.webhook_id.is_some() {
+            // Posted by webhooks, e.g. messages relayed by RORI. Forwarding them would loop
+            return;
+        }
         if msg.content == "/help" {
             let mut usage: String = String::from("Hi! I'm RORI, a free distributed chatterbot.\n");
             usage += "If you want to use this instance as another user.\n";
@@ -103,20 +516,113 @@ impl EventHandler for Handler {
             if let Err(why) = msg.channel_id.say(&ctx.http, usage).await {
                 println!("Error sending message: {:?}", why);
             }
+        } else if msg.content == "/reload" && self.admins.lock().unwrap().contains(msg.author.id.as_u64()) {
+            info!("{} asked to reload the configuration", msg.author.id);
+            let _ = self.reload_requests.send(Some(msg.channel_id));
         } else if *msg.author.id.as_u64() != *self.current_user.lock().unwrap() {
-            // TODO: for now, just forward content
-            *self.user_say.lock().unwrap() = DiscordMsg {
+            let guild_id = msg.guild_id.map(|guild_id| *guild_id.as_u64());
+            self.channels.lock().unwrap().insert(*msg.channel_id.as_u64(), guild_id);
+            let user_say = match self.user_say(msg.guild_id, msg.channel_id) {
+                Some(user_say) => user_say,
+                None => {
+                    debug!("No RORI for guild {:?}", guild_id);
+                    return;
+                }
+            };
+            if let Some((kind, notify)) = self.throttle(*msg.author.id.as_u64(), *msg.channel_id.as_u64(), guild_id) {
+                self.notify_throttled(&ctx, &msg, kind, notify).await;
+                return;
+            }
+            let (body, metadatas) = self.resolve_markups(&ctx, &msg.content, &msg.mentions, msg.guild_id).await;
+            self.bridged.lock().unwrap().add_message(msg.id);
+            self.pending.lock().unwrap().add(msg.id.as_u64().to_string(), msg.channel_id);
+            Handler::push(user_say, DiscordMsg {
                 id: msg.id.as_u64().to_string(),
-                body: msg.content.clone(),
+                body,
                 author: msg.author.id.as_u64().to_string(),
                 channel: msg.channel_id.as_u64().to_string(),
-            };
+                datatype: String::from("text/plain"),
+                metadatas,
+            });
         }
     }
 
+    async fn message_update(&self, ctx: Context, update: MessageUpdateEvent) {
+        // Only content changes are interesting for RORI (embeds resolution also trigger updates)
+        let content = match update.content {
+            Some(content) => content,
+            None => return,
+        };
+        let author = match update.author {
+            Some(author) => *author.id.as_u64(),
+            None => 0,
+        };
+        // Only user messages forwarded to RORI
+        if author == *self.current_user.lock().unwrap()
+            || self.bridged.lock().unwrap().get_message(update.id) != Some(String::new()) {
+            return;
+        }
+        let user_say = match self.user_say(update.guild_id, update.channel_id) {
+            Some(user_say) => user_say,
+            None => return,
+        };
+        let guild_id = update.guild_id.map(|guild_id| *guild_id.as_u64());
+        if let Some((kind, _)) = self.throttle(author, *update.channel_id.as_u64(), guild_id) {
+            info!("Edit of {} throttled ({} limit)", update.id, kind);
+            return;
+        }
+        let mentions = update.mentions.unwrap_or_default();
+        let (body, metadatas) = self.resolve_markups(&ctx, &content, &mentions, update.guild_id).await;
+        Handler::push(user_say, DiscordMsg {
+            id: update.id.as_u64().to_string(),
+            body,
+            author: if author != 0 { author.to_string() } else { String::new() },
+            channel: update.channel_id.as_u64().to_string(),
+            datatype: String::from("discord/edit"),
+            metadatas,
+        });
+    }
+
+    async fn message_delete(&self, _: Context, channel_id: ChannelId, deleted_message_id: MessageId) {
+        if self.bridged.lock().unwrap().get_message(deleted_message_id) != Some(String::new()) {
+            // Not a user message forwarded to RORI
+            return;
+        }
+        self.pending.lock().unwrap().remove(&deleted_message_id.as_u64().to_string());
+        let user_say = match self.user_say(None, channel_id) {
+            Some(user_say) => user_say,
+            None => return,
+        };
+        Handler::push(user_say, DiscordMsg {
+            id: deleted_message_id.as_u64().to_string(),
+            body: String::new(),
+            author: String::new(),
+            channel: channel_id.as_u64().to_string(),
+            datatype: String::from("discord/delete"),
+            metadatas: HashMap::new(),
+        });
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        if self.turn_page(&ctx, &reaction).await {
+            return;
+        }
+        self.forward_reaction(reaction, "discord/reaction-add");
+    }
+
+    async fn reaction_remove(&self, _: Context, reaction: Reaction) {
+        if Paginator::is_control(&reaction.emoji.to_string())
+            && self.paginator.lock().unwrap().contains(*reaction.message_id.as_u64()) {
+            // Page controls, removed by turn_page
+            return;
+        }
+        self.forward_reaction(reaction, "discord/reaction-remove");
+    }
+
     async fn ready(&self, _: Context, ready: Ready) {
         info!("{} is connected!", ready.user.name);
         *self.current_user.lock().unwrap() = *ready.user.id.as_u64();
+        health::set_discord_ready();
         let _ = self.sender.lock().unwrap().send(ready);
     }
 }
@@ -124,50 +630,694 @@ impl EventHandler for Handler {
 impl Bot {
     /**
      * Create a Bot instance
+     * @param token for the bot
+     * @param options
+     * @param routes the bridges to RORI
+     * @param rori_said what the RORIs of the routes say
+     * @param name_server to resolve the names of relayed users
      */
-    pub fn new(token: &str) -> Bot {
+    pub fn new(token: &str, options: BotOptions, routes: Vec<Route>, rori_said: UnboundedReceiver<(String, DiscordMsg)>,
+               name_server: NameServer) -> Bot {
+        let rate_limiter = RateLimiter::new(options.rate_limits.clone());
+        let (reload_sender, reload_requests) = unbounded_channel();
         Bot {
             ready: None,
             ready_rcv: None,
             secret_token: String::from(token),
+            bridged: Arc::new(Mutex::new(BridgedMessages::new())),
+            paginator: Arc::new(Mutex::new(Paginator::new())),
+            rate_limiter: Arc::new(Mutex::new(rate_limiter)),
+            pending: Arc::new(Mutex::new(PendingRequests::new())),
+            routes: Arc::new(Mutex::new(routes)),
+            channels: Arc::new(Mutex::new(HashMap::new())),
+            admins: Arc::new(Mutex::new(options.admins.clone())),
+            rori_said,
+            reload_requests,
+            reload_sender,
+            pending_check: tokio::time::interval(Duration::from_millis(500)),
+            name_server,
+            webhooks: HashMap::new(),
+            accepting: Arc::new(AtomicBool::new(true)),
+            options,
+        }
+    }
+
+    /**
+     * Check a Discord token against the API
+     * @param token
+     * @return the name of the bot, or an error to show
+     */
+    pub async fn check_token(token: &str) -> Result<String, String> {
+        let http = Http::new_with_token(token);
+        match http.get_current_user().await {
+            Ok(user) => Ok(user.tag()),
+            Err(why) => Err(format!("Invalid Discord token: {}", why)),
+        }
+    }
+
+    /**
+     * Apply a new configuration without reconnecting
+     * @param self
+     * @param options
+     * @param guilds route name (ring id) -> guilds handled by this route
+     * @param name_server to resolve the names of relayed users
+     */
+    pub fn reload(&mut self, options: BotOptions, guilds: HashMap<String, Vec<u64>>, name_server: NameServer) {
+        self.rate_limiter.lock().unwrap().set_config(options.rate_limits.clone());
+        *self.admins.lock().unwrap() = options.admins.clone();
+        for route in self.routes.lock().unwrap().iter_mut() {
+            if let Some(guilds) = guilds.get(&route.name) {
+                route.guilds = guilds.clone();
+            }
+        }
+        self.name_server = name_server;
+        self.options = options;
+    }
+
+    /**
+     * @param self
+     * @return a sender to ask a reload of the configuration (e.g. on SIGHUP)
+     */
+    pub fn reload_sender(&self) -> UnboundedSender<Option<ChannelId>> {
+        self.reload_sender.clone()
+    }
+
+    /**
+     * Wait for something to do. Nothing is lost if the future is dropped before the end
+     * @param self
+     * @return the next event, to give to handle_event (except reloads)
+     */
+    pub async fn next_event(&mut self) -> BotEvent {
+        tokio::select! {
+            Some((route, msg)) = self.rori_said.recv() => BotEvent::FromRori(route, msg),
+            Some(channel) = self.reload_requests.recv() => BotEvent::Reload(channel),
+            _ = self.pending_check.tick() => BotEvent::CheckPending,
+        }
+    }
+
+    /**
+     * Forward what a RORI says to Discord, or check pending requests
+     * @param self
+     * @param event from next_event
+     */
+    pub async fn handle_event(&mut self, event: BotEvent) {
+        match event {
+            BotEvent::FromRori(route, msg) => self.handle_rori_message(&route, msg).await,
+            BotEvent::CheckPending => self.handle_pending().await,
+            // Done by the Reloader, which changes the whole Bot
+            BotEvent::Reload(_) => {},
+        }
+    }
+
+    /**
+     * Post a report in some channels
+     * @param self
+     * @param channels
+     * @param text
+     */
+    pub async fn report(&self, channels: Vec<ChannelId>, text: &str) {
+        let http = Http::new_with_token(&self.secret_token);
+        for channel in channels {
+            for chunk in split_message(text, MESSAGE_MAX_LEN) {
+                if let Err(why) = channel.say(&http, chunk).await {
+                    error!("Error sending message: {:?}", why);
+                    metrics::DISCORD_ERRORS.with_label_values(&["send"]).inc();
+                }
+            }
         }
     }
 
     /**
      * Main loop of the bot, to do the bridge between RORI and Discord
      * @param self
-     * @param secret_token for the bot
-     * @param user_say, what the user say for RORI
-     * @param rori_say, what RORI say on Discord
      */
-    pub async fn run(&mut self, user_say: Arc<Mutex<DiscordMsg>>) -> serenity::Client {
+    pub async fn run(&mut self) -> serenity::Client {
         // Configure the client with your Discord bot token in the environment.
         let (sender, receiver) = mpsc::channel();
-        self.ready_rcv = Some(receiver);
+        self.ready_rcv = Some(Mutex::new(receiver));
         let sender = Arc::new(Mutex::new(sender));
-        let current_user = Arc::new(Mutex::new(0 as u64));
-        Client::new(&*self.secret_token).event_handler(Handler { user_say, sender, current_user }).await
+        let current_user = Arc::new(Mutex::new(0_u64));
+        let bridged = self.bridged.clone();
+        let paginator = self.paginator.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let pending = self.pending.clone();
+        let routes = self.routes.clone();
+        let channels = self.channels.clone();
+        let admins = self.admins.clone();
+        let reload_requests = self.reload_sender.clone();
+        let accepting = self.accepting.clone();
+        Client::builder(&*self.secret_token).event_handler(Handler {
+            routes, channels, sender, current_user, bridged, paginator, rate_limiter, pending,
+            admins, reload_requests, accepting
+        }).await
                         .expect("Error initializing RORI client")
     }
 
-    pub async fn handle_messages(&mut self, rori_say: &Arc<Mutex<DiscordMsg>>) {
-        // Forward incoming messages to discord
-        let to_say: String = String::from(&*rori_say.lock().unwrap().body.clone());
-        if !to_say.is_empty() {
-            let channel_id: String = String::from(&*rori_say.lock().unwrap().channel.clone());
-            *rori_say.lock().unwrap() = DiscordMsg::new();
-            let http = Http::new_with_token(&*self.secret_token);
-            let response = MessageBuilder::new()
-                .push(&*to_say)
-                .build();
-            if let Some(id) = self.get_channel_from_id(&channel_id).await {
-                if let Err(why) = id.say(&http, &response).await {
+    /**
+     * Stop forwarding new Discord messages, let pending messages reach RORI and Discord
+     * (until shutdown_timeout), then disconnect the shards
+     * @param self
+     * @param shard_manager of the client
+     */
+    pub async fn shutdown(&mut self, shard_manager: Arc<serenity::prelude::Mutex<ShardManager>>) {
+        info!("Shutting down, new messages are not forwarded anymore");
+        health::set_shutting_down();
+        self.accepting.store(false, Ordering::SeqCst);
+        if self.options.offline_on_shutdown {
+            let manager = shard_manager.lock().await;
+            for runner in manager.runners.lock().await.values() {
+                runner.runner_tx.set_presence(None, OnlineStatus::Offline);
+            }
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(self.options.shutdown_timeout);
+        loop {
+            // What RORIs already said
+            while let Ok((route, msg)) = self.rori_said.try_recv() {
+                self.handle_rori_message(&route, msg).await;
+            }
+            self.handle_pending().await;
+            let waiting = self.waiting_messages();
+            if waiting == 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                warn!("{} message(s) still waiting, dropped", waiting);
+                break;
+            }
+            // Wait for the answers of RORI
+            match tokio::time::timeout(Duration::from_millis(100), self.rori_said.recv()).await {
+                Ok(Some((route, msg))) => self.handle_rori_message(&route, msg).await,
+                // All the endpoints are stopped
+                Ok(None) => tokio::time::delay_for(Duration::from_millis(100)).await,
+                Err(_) => {},
+            }
+        }
+
+        shard_manager.lock().await.shutdown_all().await;
+        info!("Disconnected from Discord");
+    }
+
+    /**
+     * @param self
+     * @return the number of messages not forwarded to RORI yet, or waiting for an answer
+     */
+    fn waiting_messages(&self) -> usize {
+        let in_flight: usize = self.routes.lock().unwrap().iter()
+            .map(|route| route.in_flight.load(Ordering::SeqCst))
+            .sum();
+        in_flight + self.pending.lock().unwrap().len()
+    }
+
+    /**
+     * Forward what a RORI says to Discord
+     * @param self
+     * @param route name of the route of the RORI
+     * @param msg from RORI
+     */
+    async fn handle_rori_message(&mut self, route: &str, msg: DiscordMsg) {
+        metrics::QUEUE_DEPTH.with_label_values(&[route, "from_rori"]).dec();
+        let idx = match self.routes.lock().unwrap().iter().position(|r| r.name == route) {
+            Some(idx) => idx,
+            None => return,
+        };
+        if !self.is_allowed(idx, &msg).await {
+            warn!("{} can't talk in channel {}", route, msg.channel);
+            return;
+        }
+        let guild = msg.channel.parse::<u64>().ok()
+            .and_then(|channel| self.channels.lock().unwrap().get(&channel).cloned());
+        metrics::MESSAGES.with_label_values(&["from_rori", &*metrics::guild_label(guild)]).inc();
+        self.handle_message(msg).await;
+    }
+
+    /**
+     * Check that a RORI only talks in its guilds
+     * @param self
+     * @param idx of the route
+     * @param msg from RORI
+     * @return if the message can be posted
+     */
+    async fn is_allowed(&mut self, idx: usize, msg: &DiscordMsg) -> bool {
+        let target = match &*msg.datatype {
+            "discord/edit" | "discord/delete" => self.get_reply(msg).map(|(channel_id, _)| channel_id),
+            "discord/reaction" if msg.metadatas.contains_key("rid") => self.get_reply(msg).map(|(channel_id, _)| channel_id),
+            _ => self.get_channel_from_id(&msg.channel).await,
+        };
+        let channel_id = match target {
+            Some(channel_id) => channel_id,
+            // Nothing will be posted
+            None => return true,
+        };
+        let guild = match self.get_guild(channel_id).await {
+            Some(guild) => guild,
+            None => return false,
+        };
+        Route::find(&self.routes.lock().unwrap(), guild) == Some(idx)
+    }
+
+    /**
+     * Get the guild of a channel
+     * @param self
+     * @param channel_id
+     * @return None if unknown, else the guild (None for direct messages)
+     */
+    async fn get_guild(&mut self, channel_id: ChannelId) -> Option<Option<u64>> {
+        if let Some(guild) = self.channels.lock().unwrap().get(channel_id.as_u64()) {
+            return Some(*guild);
+        }
+        let http = Http::new_with_token(&self.secret_token);
+        let guild = match http.get_channel(*channel_id.as_u64()).await {
+            Ok(channel) => channel.guild().map(|channel| *channel.guild_id.as_u64()),
+            Err(why) => {
+                error!("Can't get channel {}: {:?}", channel_id, why);
+                metrics::DISCORD_ERRORS.with_label_values(&["get_channel"]).inc();
+                return None;
+            }
+        };
+        self.channels.lock().unwrap().insert(*channel_id.as_u64(), guild);
+        Some(guild)
+    }
+
+    /**
+     * Forward a message from RORI to Discord
+     * @param self
+     * @param msg from RORI
+     */
+    async fn handle_message(&mut self, msg: DiscordMsg) {
+        match &*msg.datatype {
+            "discord/edit" => self.edit_reply(&msg).await,
+            "discord/delete" => self.delete_reply(&msg).await,
+            "discord/reaction" => self.react(&msg).await,
+            _ => {
+                if let Ok(channel) = msg.channel.parse::<u64>() {
+                    let answered = self.pending.lock().unwrap().resolve(msg.metadatas.get("th").map(|th| th.as_str()), ChannelId::from(channel));
+                    if let Some(elapsed) = answered {
+                        metrics::RORI_REPLY_SECONDS.observe(elapsed.as_secs_f64());
+                    }
+                }
+                self.say(&msg).await
+            },
+        }
+    }
+
+    /**
+     * Show the typing indicator while RORI thinks, and tell users when RORI doesn't answer
+     * @param self
+     */
+    async fn handle_pending(&mut self) {
+        let http = Http::new_with_token(&self.secret_token);
+        let (typing, expired) = {
+            let mut pending = self.pending.lock().unwrap();
+            // With no timeout, requests are not tracked
+            let expired = pending.expired(Duration::from_secs(self.options.rori_timeout));
+            (pending.typing_channels(), expired)
+        };
+        for (th, channel) in expired {
+            if self.options.rori_timeout == 0 {
+                continue;
+            }
+            warn!("RORI didn't answer to {}", th);
+            if !self.options.rori_timeout_notice.is_empty() {
+                if let Err(why) = channel.say(&http, &self.options.rori_timeout_notice).await {
                     error!("Error sending message: {:?}", why);
+                    metrics::DISCORD_ERRORS.with_label_values(&["send"]).inc();
+                }
+            }
+        }
+        for channel in typing {
+            if let Err(why) = channel.broadcast_typing(&http).await {
+                debug!("Can't show typing indicator: {:?}", why);
+            }
+        }
+    }
+
+    /**
+     * Post a RORI message on Discord and remember it for later edits
+     * Long messages are split, or posted as a paginated embed if enabled
+     * @param self
+     * @param msg to post
+     */
+    async fn say(&mut self, msg: &DiscordMsg) {
+        if msg.body.is_empty() {
+            return;
+        }
+        let id = match self.get_channel_from_id(&msg.channel).await {
+            Some(id) => id,
+            None => return,
+        };
+        let author = self.author_name(msg).await;
+        let body = self.escape(&msg.body);
+        if !author.is_empty() && self.options.webhooks
+            && self.say_as(msg, id, &author, &split_message(&body, MESSAGE_MAX_LEN)).await {
+            return;
+        }
+        // Without webhook (disabled, direct messages or missing permission), the author prefixes the message
+        let mut response = MessageBuilder::new();
+        if !author.is_empty() {
+            response.push_bold_safe(&*author).push(": ");
+        }
+        let response = response.push(&*body).build();
+        let chunks = split_message(&response, MESSAGE_MAX_LEN);
+        if self.options.paginate_after > 0 && chunks.len() > self.options.paginate_after {
+            self.say_paginated(msg, id, &response).await;
+            return;
+        }
+        self.send_chunks(msg, id, chunks).await;
+    }
+
+    /**
+     * Post the parts of a message
+     * @param self
+     * @param msg from RORI
+     * @param id of the channel
+     * @param chunks to post
+     */
+    async fn send_chunks(&mut self, msg: &DiscordMsg, id: ChannelId, chunks: Vec<String>) {
+        let http = Http::new_with_token(&self.secret_token);
+        let mut first = true;
+        for chunk in chunks {
+            let allowed_mentions = self.allowed_mentions();
+            match id.send_message(&http, |m| {
+                m.content(chunk);
+                m.allowed_mentions(|am| {
+                    am.empty_parse();
+                    for value in allowed_mentions {
+                        am.parse(value);
+                    }
+                    am
+                })
+            }).await {
+                Ok(message) => {
+                    // Edits and deletions target the first part
+                    if first {
+                        self.remember_reply(msg, id, message.id);
+                        first = false;
+                    }
+                },
+                Err(why) => {
+                    error!("Error sending message: {:?}", why);
+                    metrics::DISCORD_ERRORS.with_label_values(&["send"]).inc();
+                    return;
+                }
+            }
+        }
+    }
+
+    /**
+     * Get the name of the Ring user relayed by RORI
+     * @param self
+     * @param msg from RORI
+     * @return the name given by RORI ("from_name"), the registered name, a short id, or empty for RORI itself
+     */
+    async fn author_name(&self, msg: &DiscordMsg) -> String {
+        if let Some(name) = msg.metadatas.get("from_name") {
+            return name.clone();
+        }
+        if msg.author.is_empty() {
+            return String::new();
+        }
+        if !self.options.rori_server.is_empty() {
+            // Cached by the name server client, also when not found
+            let name = self.name_server.get_username(&self.options.rori_server, &msg.author).await;
+            if !name.is_empty() {
+                return name;
+            }
+        }
+        msg.author.chars().take(8).collect()
+    }
+
+    /**
+     * Post a message with a webhook named as the author
+     * @param self
+     * @param msg from RORI
+     * @param id of the channel
+     * @param author name to show
+     * @param chunks to post
+     * @return false if no webhook can be used in this channel
+     */
+    async fn say_as(&mut self, msg: &DiscordMsg, id: ChannelId, author: &str, chunks: &[String]) -> bool {
+        let http = Http::new_with_token(&self.secret_token);
+        let webhook = match self.get_webhook(id).await {
+            Some(webhook) => webhook,
+            None => return false,
+        };
+        // ExecuteWebhook has no allowed_mentions builder
+        let mut allowed_mentions = CreateAllowedMentions::default();
+        allowed_mentions.empty_parse();
+        for value in self.allowed_mentions() {
+            allowed_mentions.parse(value);
+        }
+        let allowed_mentions = Value::Object(hashmap_to_json_map(allowed_mentions.0));
+        let mut first = true;
+        for chunk in chunks {
+            match webhook.execute(&http, true, |w| {
+                w.0.insert("allowed_mentions", allowed_mentions.clone());
+                w.content(chunk).username(author)
+            }).await {
+                Ok(Some(message)) => {
+                    // Only deletions work on webhook messages
+                    if first {
+                        self.remember_reply(msg, id, message.id);
+                        first = false;
+                    }
+                },
+                Ok(None) => {},
+                Err(why) => {
+                    error!("Error executing webhook: {:?}", why);
+                    metrics::DISCORD_ERRORS.with_label_values(&["webhook"]).inc();
+                    // The webhook may have been deleted
+                    self.webhooks.remove(&id);
+                    return !first;
+                }
+            }
+        }
+        true
+    }
+
+    /**
+     * Get or create the webhook of the bot in a channel
+     * @param self
+     * @param id of the channel
+     * @return the webhook if the bot can use one
+     */
+    async fn get_webhook(&mut self, id: ChannelId) -> Option<Webhook> {
+        if let Some(webhook) = self.webhooks.get(&id) {
+            return Some(webhook.clone());
+        }
+        let http = Http::new_with_token(&self.secret_token);
+        let existing = match id.webhooks(&http).await {
+            Ok(webhooks) => webhooks.into_iter().find(|w| w.name.as_deref() == Some("RORI") && !w.token.is_empty()),
+            Err(why) => {
+                debug!("Can't get webhooks of {}: {:?}", id, why);
+                return None;
+            }
+        };
+        let webhook = match existing {
+            Some(webhook) => webhook,
+            None => match http.create_webhook(*id.as_u64(), &json!({ "name": "RORI" })).await {
+                Ok(webhook) => webhook,
+                Err(why) => {
+                    warn!("Can't create a webhook in {}: {:?}", id, why);
+                    metrics::DISCORD_ERRORS.with_label_values(&["webhook"]).inc();
+                    return None;
                 }
+            },
+        };
+        self.webhooks.insert(id, webhook.clone());
+        Some(webhook)
+    }
+
+    /**
+     * Escape what RORI says before posting it
+     * @param self
+     * @param text
+     * @return the escaped text
+     */
+    fn escape(&self, text: &str) -> String {
+        escape_mentions(text, self.options.allowed_mentions.contains(&String::from("everyone")))
+    }
+
+    /**
+     * @param self
+     * @return mentions which can ping users
+     */
+    fn allowed_mentions(&self) -> Vec<ParseValue> {
+        self.options.allowed_mentions.iter().filter_map(|mention| {
+            match &**mention {
+                "users" => Some(ParseValue::Users),
+                "roles" => Some(ParseValue::Roles),
+                "everyone" => Some(ParseValue::Everyone),
+                _ => None,
             }
+        }).collect()
+    }
+
+    /**
+     * Post a long RORI message as an embed with controls to browse pages
+     * @param self
+     * @param msg to post
+     * @param id of the channel
+     * @param response the text to post
+     */
+    async fn say_paginated(&mut self, msg: &DiscordMsg, id: ChannelId, response: &str) {
+        let http = Http::new_with_token(&self.secret_token);
+        let pages = split_message(response, EMBED_MAX_LEN);
+        let footer = format!("1/{}", pages.len());
+        let first_page = pages[0].clone();
+        let message = match id.send_message(&http,
+            |m| m.embed(|e| e.description(first_page).footer(|f| f.text(footer)))).await {
+            Ok(message) => message,
+            Err(why) => {
+                error!("Error sending message: {:?}", why);
+                metrics::DISCORD_ERRORS.with_label_values(&["send"]).inc();
+                return;
+            }
+        };
+        self.remember_reply(msg, id, message.id);
+        self.paginator.lock().unwrap().add(*message.id.as_u64(), pages);
+        for control in &[PREVIOUS_PAGE, NEXT_PAGE] {
+            if let Err(why) = id.create_reaction(&http, message.id, ReactionType::Unicode(String::from(*control))).await {
+                error!("Error adding page control: {:?}", why);
+                metrics::DISCORD_ERRORS.with_label_values(&["react"]).inc();
+            }
+        }
+    }
+
+    /**
+     * Edit a previous RORI reply
+     * @param self
+     * @param msg with the new content and the "rid" of the reply to edit
+     */
+    async fn edit_reply(&mut self, msg: &DiscordMsg) {
+        let (channel_id, message_id) = match self.get_reply(msg) {
+            Some(reply) => reply,
+            None => {
+                warn!("Can't edit unknown reply {:?}", msg.metadatas.get("rid"));
+                return;
+            }
+        };
+        let http = Http::new_with_token(&self.secret_token);
+        let content = MessageBuilder::new()
+            .push(&*self.escape(&msg.body))
+            .build();
+        if let Err(why) = channel_id.edit_message(&http, message_id, |m| m.content(content)).await {
+            error!("Error editing message: {:?}", why);
+            metrics::DISCORD_ERRORS.with_label_values(&["edit"]).inc();
         }
     }
 
+    /**
+     * Delete a previous RORI reply
+     * @param self
+     * @param msg with the "rid" of the reply to delete
+     */
+    async fn delete_reply(&mut self, msg: &DiscordMsg) {
+        let (channel_id, message_id) = match self.get_reply(msg) {
+            Some(reply) => reply,
+            None => {
+                warn!("Can't delete unknown reply {:?}", msg.metadatas.get("rid"));
+                return;
+            }
+        };
+        let http = Http::new_with_token(&self.secret_token);
+        if let Err(why) = channel_id.delete_message(&http, message_id).await {
+            error!("Error deleting message: {:?}", why);
+            metrics::DISCORD_ERRORS.with_label_values(&["delete"]).inc();
+        }
+        if let Some(rid) = msg.metadatas.get("rid") {
+            self.bridged.lock().unwrap().remove_reply(rid);
+        }
+    }
+
+    /**
+     * Add a reaction to a reply (via "rid") or to any message (via "th" and "ch")
+     * @param self
+     * @param msg with the emoji as body
+     */
+    async fn react(&mut self, msg: &DiscordMsg) {
+        let target = match self.get_reply(msg) {
+            Some(reply) => Some(reply),
+            None => {
+                let message_id = msg.metadatas.get("th").and_then(|th| th.parse::<u64>().ok());
+                let channel_id = msg.channel.parse::<u64>().ok();
+                match (channel_id, message_id) {
+                    (Some(channel_id), Some(message_id)) => Some((ChannelId::from(channel_id), MessageId::from(message_id))),
+                    _ => None,
+                }
+            }
+        };
+        let (channel_id, message_id) = match target {
+            Some(target) => target,
+            None => {
+                warn!("Can't find the message to react to");
+                return;
+            }
+        };
+        let reaction = match Bot::parse_reaction(&msg.body) {
+            Some(reaction) => reaction,
+            None => {
+                warn!("Invalid reaction: {}", msg.body);
+                return;
+            }
+        };
+        let http = Http::new_with_token(&self.secret_token);
+        if let Err(why) = channel_id.create_reaction(&http, message_id, reaction).await {
+            error!("Error adding reaction: {:?}", why);
+            metrics::DISCORD_ERRORS.with_label_values(&["react"]).inc();
+        }
+    }
+
+    /**
+     * Parse an emoji from RORI. Custom emojis use the Discord format <:name:id> or <a:name:id>
+     * @param emoji
+     * @return the reaction if valid
+     */
+    fn parse_reaction(emoji: &str) -> Option<ReactionType> {
+        let emoji = emoji.trim();
+        if emoji.is_empty() {
+            return None;
+        }
+        if !emoji.starts_with('<') || !emoji.ends_with('>') {
+            return Some(ReactionType::Unicode(String::from(emoji)));
+        }
+        let parts: Vec<&str> = emoji[1..emoji.len()-1].split(':').collect();
+        if parts.len() != 3 {
+            return None;
+        }
+        let id = parts[2].parse::<u64>().ok()?;
+        Some(ReactionType::Custom {
+            animated: parts[0] == "a",
+            id: EmojiId::from(id),
+            name: Some(String::from(parts[1])),
+        })
+    }
+
+    /**
+     * Remember a posted reply. The key is the "rid" given by RORI if any, else the id of the interaction
+     * @param self
+     * @param msg posted
+     * @param channel_id where the message was posted
+     * @param message_id of the Discord message
+     */
+    fn remember_reply(&mut self, msg: &DiscordMsg, channel_id: ChannelId, message_id: MessageId) {
+        let rid = match msg.metadatas.get("rid") {
+            Some(rid) => rid.clone(),
+            None => msg.id.clone(),
+        };
+        self.bridged.lock().unwrap().add_reply(&rid, channel_id, message_id);
+    }
+
+    /**
+     * Retrieve a reply from the "rid" metadata of a message
+     * @param self
+     * @param msg
+     * @return the channel and id of the Discord message if found
+     */
+    fn get_reply(&self, msg: &DiscordMsg) -> Option<(ChannelId, MessageId)> {
+        let rid = msg.metadatas.get("rid")?;
+        self.bridged.lock().unwrap().get_reply(rid)
+    }
 
     /**
      * Retrieve a channel from an id
@@ -175,22 +1325,22 @@ impl Bot {
      * @param id
      * @return the Channel if found, else the default channel if ready or None if not ready.
      */
-    async fn get_channel_from_id(&mut self, id: &String) -> Option<ChannelId> {
-        if !self.ready_rcv.is_some() {
+    async fn get_channel_from_id(&mut self, id: &str) -> Option<ChannelId> {
+        if self.ready_rcv.is_none() {
             error!("ready_rcv should not be none");
             return None;
         }
-        if !self.ready.is_some() {
-            self.ready = Some(self.ready_rcv.as_ref().unwrap().recv().unwrap());
+        if self.ready.is_none() {
+            self.ready = Some(self.ready_rcv.as_ref().unwrap().lock().unwrap().recv().unwrap());
         }
-        let http = Http::new_with_token(&*self.secret_token);
+        let http = Http::new_with_token(&self.secret_token);
         let id = id.parse::<u64>().unwrap_or(0);
         if id != 0 {
             return Some(ChannelId::from(id));
         }
         for guild in &self.ready.as_ref().unwrap().guilds {
             let server_name = guild.id().to_partial_guild(&http).await.unwrap().name;
-            for (chan_id, chan) in guild.id().channels(&http).await.ok().expect("No channels!") {
+            for (chan_id, chan) in guild.id().channels(&http).await.expect("No channels!") {
                 // TODO default channel configuration!
                 if server_name == "RORI" && chan.name() == "general" {
                     return Some(chan_id)
@@ -200,3 +1350,65 @@ impl Bot {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(name: &str, guilds: Vec<u64>) -> Route {
+        let (user_say, _) = tokio::sync::mpsc::unbounded_channel();
+        Route {
+            name: String::from(name),
+            guilds,
+            user_say,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    #[test]
+    fn find_route_of_guild() {
+        let routes = vec![route("first", vec![1, 2]), route("second", vec![3])];
+        assert_eq!(Route::find(&routes, Some(1)), Some(0));
+        assert_eq!(Route::find(&routes, Some(2)), Some(0));
+        assert_eq!(Route::find(&routes, Some(3)), Some(1));
+    }
+
+    #[test]
+    fn no_cross_talk_between_guilds() {
+        let routes = vec![route("first", vec![1]), route("second", vec![2])];
+        // Without default route, other guilds and direct messages go nowhere
+        assert_eq!(Route::find(&routes, Some(3)), None);
+        assert_eq!(Route::find(&routes, None), None);
+        assert_ne!(Route::find(&routes, Some(1)), Route::find(&routes, Some(2)));
+    }
+
+    #[test]
+    fn default_route_takes_the_rest() {
+        let routes = vec![route("first", vec![1]), route("default", vec![]), route("second", vec![2])];
+        assert_eq!(Route::find(&routes, Some(2)), Some(2));
+        assert_eq!(Route::find(&routes, Some(3)), Some(1));
+        assert_eq!(Route::find(&routes, None), Some(1));
+    }
+
+    #[test]
+    fn parse_unicode_reactions() {
+        assert_eq!(Bot::parse_reaction(" 👍 "), Some(ReactionType::Unicode(String::from("👍"))));
+        assert_eq!(Bot::parse_reaction(""), None);
+    }
+
+    #[test]
+    fn parse_custom_reactions() {
+        assert_eq!(Bot::parse_reaction("<:rori:123>"), Some(ReactionType::Custom {
+            animated: false,
+            id: EmojiId::from(123),
+            name: Some(String::from("rori")),
+        }));
+        assert_eq!(Bot::parse_reaction("<a:dance:42>"), Some(ReactionType::Custom {
+            animated: true,
+            id: EmojiId::from(42),
+            name: Some(String::from("dance")),
+        }));
+        assert_eq!(Bot::parse_reaction("<:rori>"), None);
+        assert_eq!(Bot::parse_reaction("<:rori:id>"), None);
+    }
+}
@@ -0,0 +1,166 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use std::collections::{HashMap, VecDeque};
+
+/**
+ * Reactions used to browse pages
+ */
+pub const PREVIOUS_PAGE: &str = "◀️";
+pub const NEXT_PAGE: &str = "▶️";
+
+/**
+ * Max number of paginated messages remembered
+ */
+const MAX_PAGINATED_MESSAGES: usize = 100;
+
+/**
+ * Remember the pages of long RORI replies posted as paginated embeds
+ **/
+#[derive(Debug)]
+pub struct Paginator {
+    // Discord message id -> (pages, current page)
+    messages: HashMap<u64, (Vec<String>, usize)>,
+    order: VecDeque<u64>,
+}
+
+impl Paginator {
+    pub fn new() -> Paginator {
+        Paginator {
+            messages: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /**
+     * Remember a paginated message
+     * @param self
+     * @param message_id
+     * @param pages
+     */
+    pub fn add(&mut self, message_id: u64, pages: Vec<String>) {
+        if self.messages.insert(message_id, (pages, 0)).is_none() {
+            self.order.push_back(message_id);
+        }
+        while self.order.len() > MAX_PAGINATED_MESSAGES {
+            if let Some(old) = self.order.pop_front() {
+                self.messages.remove(&old);
+            }
+        }
+    }
+
+    /**
+     * Move to another page
+     * @param self
+     * @param message_id
+     * @param emoji the reaction used
+     * @return (page, current page, number of pages) if the page changed
+     */
+    pub fn turn(&mut self, message_id: u64, emoji: &str) -> Option<(String, usize, usize)> {
+        let (pages, current) = self.messages.get_mut(&message_id)?;
+        let wanted = match emoji {
+            PREVIOUS_PAGE if *current > 0 => *current - 1,
+            NEXT_PAGE if *current + 1 < pages.len() => *current + 1,
+            _ => return None,
+        };
+        *current = wanted;
+        Some((pages[wanted].clone(), wanted, pages.len()))
+    }
+
+    /**
+     * @param emoji of a reaction
+     * @return if the reaction is used to browse pages
+     */
+    pub fn is_control(emoji: &str) -> bool {
+        emoji == PREVIOUS_PAGE || emoji == NEXT_PAGE
+    }
+
+    /**
+     * @param self
+     * @param message_id
+     * @return if the message is paginated
+     */
+    pub fn contains(&self, message_id: u64) -> bool {
+        self.messages.contains_key(&message_id)
+    }
+}
+
+impl Default for Paginator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pages() -> Vec<String> {
+        vec![String::from("one"), String::from("two"), String::from("three")]
+    }
+
+    #[test]
+    fn turn_pages() {
+        let mut paginator = Paginator::new();
+        paginator.add(1, pages());
+        assert_eq!(paginator.turn(1, NEXT_PAGE), Some((String::from("two"), 1, 3)));
+        assert_eq!(paginator.turn(1, NEXT_PAGE), Some((String::from("three"), 2, 3)));
+        assert_eq!(paginator.turn(1, PREVIOUS_PAGE), Some((String::from("two"), 1, 3)));
+    }
+
+    #[test]
+    fn stay_in_bounds() {
+        let mut paginator = Paginator::new();
+        paginator.add(1, pages());
+        assert_eq!(paginator.turn(1, PREVIOUS_PAGE), None);
+        paginator.turn(1, NEXT_PAGE);
+        paginator.turn(1, NEXT_PAGE);
+        assert_eq!(paginator.turn(1, NEXT_PAGE), None);
+        assert_eq!(paginator.turn(1, PREVIOUS_PAGE), Some((String::from("two"), 1, 3)));
+    }
+
+    #[test]
+    fn only_controls_turn_pages() {
+        let mut paginator = Paginator::new();
+        paginator.add(1, pages());
+        assert!(!Paginator::is_control("👍"));
+        assert_eq!(paginator.turn(1, "👍"), None);
+        assert_eq!(paginator.turn(2, NEXT_PAGE), None);
+    }
+
+    #[test]
+    fn oldest_messages_are_evicted() {
+        let mut paginator = Paginator::new();
+        for id in 0..MAX_PAGINATED_MESSAGES as u64 + 1 {
+            paginator.add(id, pages());
+        }
+        assert!(!paginator.contains(0));
+        assert!(paginator.contains(1));
+        assert!(paginator.contains(MAX_PAGINATED_MESSAGES as u64));
+    }
+}
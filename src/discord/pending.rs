@@ -0,0 +1,229 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use serenity::model::id::ChannelId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/**
+ * Discord shows the typing indicator for 10 seconds
+ */
+const TYPING_INTERVAL: Duration = Duration::from_secs(8);
+
+#[derive(Debug)]
+struct PendingRequest {
+    channel: ChannelId,
+    since: Instant,
+}
+
+/**
+ * Messages forwarded to RORI still waiting for an answer, by th (Discord message id)
+ **/
+#[derive(Debug)]
+pub struct PendingRequests {
+    requests: HashMap<String, PendingRequest>,
+    last_typing: HashMap<ChannelId, Instant>,
+}
+
+impl PendingRequests {
+    pub fn new() -> PendingRequests {
+        PendingRequests {
+            requests: HashMap::new(),
+            last_typing: HashMap::new(),
+        }
+    }
+
+    /**
+     * Wait for an answer for a message
+     * @param self
+     * @param th the Discord message id
+     * @param channel where to show the typing indicator
+     */
+    pub fn add(&mut self, th: String, channel: ChannelId) {
+        self.requests.insert(th, PendingRequest {
+            channel,
+            since: Instant::now(),
+        });
+    }
+
+    /**
+     * Stop waiting for a message (answered or deleted)
+     * If the answer doesn't reference a message, the oldest request of the channel is answered
+     * @param self
+     * @param th the Discord message id if known
+     * @param channel of the answer
+     * @return the time waited for the answer, if a request was answered
+     */
+    pub fn resolve(&mut self, th: Option<&str>, channel: ChannelId) -> Option<Duration> {
+        let th = match th {
+            Some(th) if self.requests.contains_key(th) => Some(th.to_string()),
+            _ => self.requests.iter()
+                    .filter(|(_, request)| request.channel == channel)
+                    .min_by_key(|(_, request)| request.since)
+                    .map(|(th, _)| th.clone()),
+        };
+        self.remove(&th?)
+    }
+
+    /**
+     * Stop waiting for a message
+     * @param self
+     * @param th the Discord message id
+     * @return the time waited, if the message was waited
+     */
+    pub fn remove(&mut self, th: &str) -> Option<Duration> {
+        let request = self.requests.remove(th)?;
+        if !self.requests.values().any(|other| other.channel == request.channel) {
+            self.last_typing.remove(&request.channel);
+        }
+        Some(request.since.elapsed())
+    }
+
+    /**
+     * @param self
+     * @return channels where the typing indicator must be sent again
+     */
+    pub fn typing_channels(&mut self) -> Vec<ChannelId> {
+        let now = Instant::now();
+        let mut channels = Vec::new();
+        for request in self.requests.values() {
+            if channels.contains(&request.channel) {
+                continue;
+            }
+            let expired = match self.last_typing.get(&request.channel) {
+                Some(last) => now.duration_since(*last) >= TYPING_INTERVAL,
+                None => true,
+            };
+            if expired {
+                channels.push(request.channel);
+            }
+        }
+        for channel in &channels {
+            self.last_typing.insert(*channel, now);
+        }
+        channels
+    }
+
+    /**
+     * Stop waiting for requests older than timeout
+     * @param self
+     * @param timeout
+     * @return (th, channel) of the expired requests
+     */
+    pub fn expired(&mut self, timeout: Duration) -> Vec<(String, ChannelId)> {
+        let now = Instant::now();
+        let expired: Vec<(String, ChannelId)> = self.requests.iter()
+            .filter(|(_, request)| now.duration_since(request.since) >= timeout)
+            .map(|(th, request)| (th.clone(), request.channel))
+            .collect();
+        for (th, _) in &expired {
+            self.remove(th);
+        }
+        expired
+    }
+
+    /**
+     * @param self
+     * @return the number of requests waiting for an answer
+     */
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /**
+     * @param self
+     * @return if no request is waiting for an answer
+     */
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
+
+impl Default for PendingRequests {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn resolve_known_request() {
+        let mut pending = PendingRequests::new();
+        pending.add(String::from("1"), ChannelId::from(10));
+        pending.add(String::from("2"), ChannelId::from(10));
+        assert!(pending.resolve(Some("2"), ChannelId::from(10)).is_some());
+        assert_eq!(pending.len(), 1);
+        assert!(pending.remove("1").is_some());
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn resolve_oldest_of_the_channel() {
+        let mut pending = PendingRequests::new();
+        pending.add(String::from("1"), ChannelId::from(10));
+        thread::sleep(Duration::from_millis(5));
+        pending.add(String::from("2"), ChannelId::from(10));
+        pending.add(String::from("3"), ChannelId::from(20));
+        // Without th, or with an unknown th
+        assert!(pending.resolve(None, ChannelId::from(10)).is_some());
+        assert!(pending.resolve(Some("42"), ChannelId::from(10)).is_some());
+        assert_eq!(pending.expired(Duration::from_secs(0)), vec![(String::from("3"), ChannelId::from(20))]);
+    }
+
+    #[test]
+    fn resolve_nothing_in_other_channels() {
+        let mut pending = PendingRequests::new();
+        pending.add(String::from("1"), ChannelId::from(10));
+        assert!(pending.resolve(None, ChannelId::from(20)).is_none());
+        assert!(pending.resolve(Some("2"), ChannelId::from(20)).is_none());
+        assert_eq!(pending.len(), 1);
+    }
+
+    #[test]
+    fn expire_old_requests() {
+        let mut pending = PendingRequests::new();
+        pending.add(String::from("1"), ChannelId::from(10));
+        assert!(pending.expired(Duration::from_secs(60)).is_empty());
+        assert_eq!(pending.expired(Duration::from_secs(0)), vec![(String::from("1"), ChannelId::from(10))]);
+        assert!(pending.is_empty());
+        assert!(pending.resolve(None, ChannelId::from(10)).is_none());
+    }
+
+    #[test]
+    fn typing_once_per_channel() {
+        let mut pending = PendingRequests::new();
+        pending.add(String::from("1"), ChannelId::from(10));
+        pending.add(String::from("2"), ChannelId::from(10));
+        assert_eq!(pending.typing_channels(), vec![ChannelId::from(10)]);
+        assert!(pending.typing_channels().is_empty());
+    }
+}
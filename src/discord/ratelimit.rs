@@ -0,0 +1,340 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+
+/**
+ * Max number of buckets per kind before forgetting the full ones
+ */
+const MAX_BUCKETS: usize = 10000;
+
+/**
+ * Configuration of a token bucket: up to capacity messages in a burst,
+ * then per_second messages per second.
+ **/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BucketConfig {
+    pub capacity: f64,
+    pub per_second: f64,
+}
+
+/**
+ * Limits applied before forwarding messages to RORI
+ **/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RateLimitsConfig {
+    // Message posted in the channel when throttled. Empty to disable
+    #[serde(default)]
+    pub notice: String,
+    // Reaction added to throttled messages. Empty to disable
+    #[serde(default)]
+    pub reaction: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<BucketConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<BucketConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guild: Option<BucketConfig>,
+}
+
+impl RateLimitsConfig {
+    pub fn new() -> RateLimitsConfig {
+        RateLimitsConfig {
+            notice: String::new(),
+            reaction: String::new(),
+            user: None,
+            channel: None,
+            guild: None,
+        }
+    }
+}
+
+impl Default for RateLimitsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+ * Why a message was throttled
+ **/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Throttle {
+    User,
+    Channel,
+    Guild,
+}
+
+// Used for println!
+impl fmt::Display for Throttle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Throttle::User => write!(f, "user"),
+            Throttle::Channel => write!(f, "channel"),
+            Throttle::Guild => write!(f, "guild"),
+        }
+    }
+}
+
+/**
+ * Counters for monitoring
+ **/
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitStats {
+    pub forwarded: u64,
+    pub throttled_user: u64,
+    pub throttled_channel: u64,
+    pub throttled_guild: u64,
+}
+
+// Used for println!
+impl fmt::Display for RateLimitStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "forwarded: {}, throttled (user: {}, channel: {}, guild: {})",
+            self.forwarded, self.throttled_user, self.throttled_channel, self.throttled_guild)
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+    // If the user was already told to slow down
+    notified: bool,
+}
+
+impl TokenBucket {
+    fn new(config: &BucketConfig) -> TokenBucket {
+        TokenBucket {
+            tokens: config.capacity,
+            last: Instant::now(),
+            notified: false,
+        }
+    }
+
+    fn refill(&mut self, config: &BucketConfig) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last);
+        let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.;
+        self.tokens = (self.tokens + elapsed * config.per_second).min(config.capacity);
+        self.last = now;
+    }
+
+    fn is_full(&self, config: &BucketConfig) -> bool {
+        self.tokens >= config.capacity
+    }
+}
+
+/**
+ * Token buckets per Discord user, channel and guild
+ **/
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitsConfig,
+    users: HashMap<u64, TokenBucket>,
+    channels: HashMap<u64, TokenBucket>,
+    guilds: HashMap<u64, TokenBucket>,
+    stats: RateLimitStats,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitsConfig) -> RateLimiter {
+        RateLimiter {
+            config,
+            users: HashMap::new(),
+            channels: HashMap::new(),
+            guilds: HashMap::new(),
+            stats: RateLimitStats::default(),
+        }
+    }
+
+    /**
+     * Change the limits. Current buckets are kept
+     * @param self
+     * @param config
+     */
+    pub fn set_config(&mut self, config: RateLimitsConfig) {
+        self.config = config;
+    }
+
+    /**
+     * @param self
+     * @return the current limits
+     */
+    pub fn config(&self) -> &RateLimitsConfig {
+        &self.config
+    }
+
+    /**
+     * @param self
+     * @return counters since the start
+     */
+    pub fn stats(&self) -> RateLimitStats {
+        self.stats.clone()
+    }
+
+    /**
+     * Take a token for a message if all buckets allow it
+     * @param self
+     * @param user
+     * @param channel
+     * @param guild if not a direct message
+     * @return Ok if the message can be forwarded, else why it's throttled and if the
+     * user should be notified (only the first time)
+     */
+    pub fn try_acquire(&mut self, user: u64, channel: u64, guild: Option<u64>) -> Result<(), (Throttle, bool)> {
+        let mut checks = vec![(Throttle::User, user), (Throttle::Channel, channel)];
+        if let Some(guild) = guild {
+            checks.push((Throttle::Guild, guild));
+        }
+        for (kind, id) in &checks {
+            let (config, buckets) = match self.buckets(*kind) {
+                Some(buckets) => buckets,
+                None => continue,
+            };
+            let bucket = buckets.entry(*id).or_insert_with(|| TokenBucket::new(&config));
+            bucket.refill(&config);
+            if bucket.tokens < 1. {
+                let notify = !bucket.notified;
+                bucket.notified = true;
+                match kind {
+                    Throttle::User => self.stats.throttled_user += 1,
+                    Throttle::Channel => self.stats.throttled_channel += 1,
+                    Throttle::Guild => self.stats.throttled_guild += 1,
+                }
+                return Err((*kind, notify));
+            }
+        }
+        for (kind, id) in &checks {
+            if let Some((_, buckets)) = self.buckets(*kind) {
+                if let Some(bucket) = buckets.get_mut(id) {
+                    bucket.tokens -= 1.;
+                    bucket.notified = false;
+                }
+            }
+        }
+        self.stats.forwarded += 1;
+        self.prune();
+        Ok(())
+    }
+
+    fn buckets(&mut self, kind: Throttle) -> Option<(BucketConfig, &mut HashMap<u64, TokenBucket>)> {
+        match kind {
+            Throttle::User => Some((self.config.user.clone()?, &mut self.users)),
+            Throttle::Channel => Some((self.config.channel.clone()?, &mut self.channels)),
+            Throttle::Guild => Some((self.config.guild.clone()?, &mut self.guilds)),
+        }
+    }
+
+    /**
+     * Forget full buckets, they are equivalent to new ones
+     * @param self
+     */
+    fn prune(&mut self) {
+        for kind in &[Throttle::User, Throttle::Channel, Throttle::Guild] {
+            if let Some((config, buckets)) = self.buckets(*kind) {
+                if buckets.len() > MAX_BUCKETS {
+                    buckets.retain(|_, bucket| {
+                        bucket.refill(&config);
+                        !bucket.is_full(&config)
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn limiter(user: Option<BucketConfig>, channel: Option<BucketConfig>) -> RateLimiter {
+        let mut config = RateLimitsConfig::new();
+        config.user = user;
+        config.channel = channel;
+        RateLimiter::new(config)
+    }
+
+    fn bucket(capacity: f64, per_second: f64) -> Option<BucketConfig> {
+        Some(BucketConfig { capacity, per_second })
+    }
+
+    #[test]
+    fn unlimited_without_buckets() {
+        let mut rate_limiter = limiter(None, None);
+        for _ in 0..100 {
+            assert_eq!(rate_limiter.try_acquire(1, 2, Some(3)), Ok(()));
+        }
+        assert_eq!(rate_limiter.stats().forwarded, 100);
+    }
+
+    #[test]
+    fn throttle_after_burst() {
+        let mut rate_limiter = limiter(bucket(2., 0.001), None);
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Ok(()));
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Ok(()));
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Err((Throttle::User, true)));
+        // Other users have their own bucket
+        assert_eq!(rate_limiter.try_acquire(4, 2, None), Ok(()));
+        let stats = rate_limiter.stats();
+        assert_eq!((stats.forwarded, stats.throttled_user), (3, 1));
+    }
+
+    #[test]
+    fn notify_once() {
+        let mut rate_limiter = limiter(None, bucket(1., 0.001));
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Ok(()));
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Err((Throttle::Channel, true)));
+        assert_eq!(rate_limiter.try_acquire(3, 2, None), Err((Throttle::Channel, false)));
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Err((Throttle::Channel, false)));
+    }
+
+    #[test]
+    fn refill_over_time() {
+        let mut rate_limiter = limiter(bucket(1., 20.), None);
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Ok(()));
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Err((Throttle::User, true)));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Ok(()));
+        // Notified again after a forwarded message
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Err((Throttle::User, true)));
+    }
+
+    #[test]
+    fn throttled_messages_take_no_token() {
+        let mut rate_limiter = limiter(bucket(2., 0.001), bucket(1., 0.001));
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Ok(()));
+        assert_eq!(rate_limiter.try_acquire(1, 2, None), Err((Throttle::Channel, true)));
+        // The user bucket still has a token
+        assert_eq!(rate_limiter.try_acquire(1, 3, None), Ok(()));
+    }
+}
@@ -0,0 +1,189 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use crate::config::ConfigFile;
+use crate::discord::Bot;
+use crate::rori::daemon::DaemonBus;
+use crate::rori::endpoint::Endpoint;
+use crate::rori::nameserver::NameServer;
+use std::path::Path;
+
+/**
+ * Pass/fail report printed by check-config
+ */
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn new() -> Report {
+        Report {
+            failures: 0,
+        }
+    }
+
+    fn check(&mut self, what: &str, result: Result<String, String>) -> bool {
+        match result {
+            Ok(details) => {
+                if details.is_empty() {
+                    println!("[ OK ] {}", what);
+                } else {
+                    println!("[ OK ] {}: {}", what, details);
+                }
+                true
+            },
+            Err(why) => {
+                println!("[FAIL] {}: {}", what, why);
+                self.failures += 1;
+                false
+            }
+        }
+    }
+
+    fn skip(&self, what: &str) {
+        println!("[SKIP] {}", what);
+    }
+}
+
+/**
+ * Check the config file, the daemon, the Ring accounts, the RORIs and the Discord token
+ * @param config_path
+ * @return if every check passed
+ */
+pub async fn check_config(config_path: &Path) -> bool {
+    let mut report = Report::new();
+
+    // 1. Config file
+    let config = match ConfigFile::load(config_path) {
+        Ok(config) => config,
+        Err(why) => {
+            report.check("Config file", Err(why));
+            return false;
+        }
+    };
+    let problems = config.check();
+    let config_ok = problems.is_empty();
+    report.check("Config file", if config_ok {
+        Ok(config_path.display().to_string())
+    } else {
+        Err(problems.join(", "))
+    });
+
+    let name_server = NameServer::new(&config.tls, &config.name_server);
+    report.check("Name server client", name_server.as_ref().map(|_| {
+        if config.tls.insecure { String::from("certificates NOT verified (tls.insecure)") } else { String::new() }
+    }).map_err(|why| why.clone()));
+
+    // 2. Daemon
+    let daemon_ok = report.check(&format!("{} on the session bus", DaemonBus::current().name),
+        Endpoint::ping_daemon().await.map(|count| format!("{} account(s)", count)));
+
+    // 3. Accounts and RORIs of each bridge
+    let accounts = if daemon_ok { Endpoint::get_account_list().await } else { Vec::new() };
+    for bridge in config.get_bridges() {
+        let account_label = format!("Ring account {}", bridge.ring_id);
+        if !daemon_ok {
+            report.skip(&account_label);
+            continue;
+        }
+        let account = match accounts.iter().find(|account| account.id == bridge.ring_id) {
+            Some(account) => account,
+            None => {
+                report.check(&account_label, Err(String::from("not found in the daemon")));
+                continue;
+            }
+        };
+        report.check(&account_label, if account.enabled {
+            Ok(format!("{} ({})", account.ring_id, account.alias))
+        } else {
+            Err(String::from("disabled"))
+        });
+        report.check(&format!("{} registered", account_label),
+            Endpoint::get_volatile_details(&account.id).await.and_then(|details| {
+                let status = details.get("Account.registrationStatus").cloned().unwrap_or_default();
+                if status == "REGISTERED" { Ok(String::new()) } else { Err(status) }
+            }));
+
+        let mut roris = vec![bridge.rori_ring_id.clone()];
+        for rori_override in &bridge.overrides {
+            let rori_ring_id = match &name_server {
+                Ok(name_server) => name_server.get_ring_id(&rori_override.rori_server, &rori_override.rori_name).await,
+                Err(_) => String::new(),
+            };
+            if report.check(&format!("{} resolves on {}", rori_override.rori_name, rori_override.rori_server),
+                            if rori_ring_id.is_empty() { Err(String::from("not found")) } else { Ok(rori_ring_id.clone()) }) {
+                roris.push(rori_ring_id);
+            }
+        }
+        let contacts = Endpoint::get_contacts(&account.id).await;
+        for rori in roris {
+            report.check(&format!("{} trusts RORI {}", account_label, rori), match &contacts {
+                Ok(contacts) => {
+                    let rori = rori.replace("ring:", "").to_lowercase();
+                    match contacts.iter().find(|c| c.get("id").map(|id| id.to_lowercase() == rori).unwrap_or(false)) {
+                        Some(contact) if contact.get("confirmed").map(|c| c == "true").unwrap_or(false) => Ok(String::new()),
+                        Some(_) => Err(String::from("trust request not confirmed yet")),
+                        None => Err(String::from("not a contact")),
+                    }
+                },
+                Err(why) => Err(why.clone()),
+            });
+        }
+    }
+
+    // 4. Default RORI name
+    if config.rori_server.is_empty() {
+        report.skip("RORI name (no rori_server)");
+    } else {
+        let rori_ring_id = match &name_server {
+            Ok(name_server) => name_server.get_ring_id(&config.rori_server, &config.rori_name).await,
+            Err(_) => String::new(),
+        };
+        report.check(&format!("{} resolves on {}", config.rori_name, config.rori_server), if rori_ring_id.is_empty() {
+            Err(String::from("not found"))
+        } else if !config.rori_ring_id.is_empty() && rori_ring_id != config.rori_ring_id {
+            Err(format!("{} but rori_ring_id is {}", rori_ring_id, config.rori_ring_id))
+        } else {
+            Ok(rori_ring_id)
+        });
+    }
+
+    // 5. Discord
+    if config.discord_secret_token.is_empty() {
+        report.skip("Discord token (empty)");
+    } else {
+        report.check("Discord token", Bot::check_token(&config.discord_secret_token).await);
+    }
+
+    if report.failures > 0 {
+        println!("{} check(s) failed", report.failures);
+    } else {
+        println!("Everything looks good");
+    }
+    report.failures == 0
+}
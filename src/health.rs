@@ -0,0 +1,266 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use serde_json::{json, Map, Value};
+use serenity::client::bridge::gateway::ShardManager;
+use serenity::gateway::ConnectionStage;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/**
+ * The D-Bus listener is dead if its loop didn't run for this duration
+ */
+const LISTENER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+ * State of a bridge, updated by its endpoint
+ */
+#[derive(Debug, Clone)]
+struct BridgeHealth {
+    loaded: bool,
+    registration: String,
+    roris: Vec<String>,
+    // Ring ids of the trusted contacts of the account, or why they can't be read
+    contacts: Result<Vec<String>, String>,
+}
+
+struct State {
+    discord_ready: bool,
+    shutting_down: bool,
+    shard_manager: Option<Arc<serenity::prelude::Mutex<ShardManager>>>,
+    // Signals of the daemon are listened for all the bridges
+    listening: bool,
+    heartbeat: Option<Instant>,
+    // Ring account id -> bridge
+    bridges: HashMap<String, BridgeHealth>,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State {
+        discord_ready: false,
+        shutting_down: false,
+        shard_manager: None,
+        listening: false,
+        heartbeat: None,
+        bridges: HashMap::new(),
+    });
+}
+
+/**
+ * Declare a bridge, not ready until its account is loaded
+ * @param account id of the bridge
+ */
+pub fn add_bridge(account: &str) {
+    STATE.lock().unwrap().bridges.insert(String::from(account), BridgeHealth {
+        loaded: false,
+        registration: String::new(),
+        roris: Vec::new(),
+        contacts: Err(String::from("contacts not loaded")),
+    });
+}
+
+/**
+ * Update a bridge, if declared
+ * @param account id of the bridge
+ * @param update
+ */
+fn update_bridge<F: FnOnce(&mut BridgeHealth)>(account: &str, update: F) {
+    if let Some(bridge) = STATE.lock().unwrap().bridges.get_mut(account) {
+        update(bridge);
+    }
+}
+
+/**
+ * @param account id of the bridge, loaded from the daemon
+ */
+pub fn set_loaded(account: &str) {
+    update_bridge(account, |bridge| bridge.loaded = true);
+}
+
+/**
+ * @param listening if the signals of the daemon are listened
+ */
+pub fn set_listening(listening: bool) {
+    let mut state = STATE.lock().unwrap();
+    state.listening = listening;
+    state.heartbeat = Some(Instant::now());
+}
+
+/**
+ * Called by the loop of the D-Bus listener
+ */
+pub fn heartbeat() {
+    STATE.lock().unwrap().heartbeat = Some(Instant::now());
+}
+
+/**
+ * @param account id of the bridge
+ * @param state Account.registrationStatus
+ */
+pub fn set_registration(account: &str, state: &str) {
+    update_bridge(account, |bridge| bridge.registration = String::from(state));
+}
+
+/**
+ * @param account id of the bridge
+ * @param roris ring ids of the RORIs of the bridge
+ */
+pub fn set_roris(account: &str, roris: Vec<String>) {
+    update_bridge(account, |bridge| bridge.roris = roris);
+}
+
+/**
+ * @param account id of the bridge
+ * @param contacts ring ids of the trusted contacts, or why they can't be read
+ */
+pub fn set_contacts(account: &str, contacts: Result<Vec<String>, String>) {
+    update_bridge(account, |bridge| bridge.contacts = contacts);
+}
+
+/**
+ * Called when the Discord client is ready
+ */
+pub fn set_discord_ready() {
+    STATE.lock().unwrap().discord_ready = true;
+}
+
+/**
+ * Called when the bot stops: not ready anymore
+ */
+pub fn set_shutting_down() {
+    STATE.lock().unwrap().shutting_down = true;
+}
+
+/**
+ * @param shard_manager of the Discord client, to check the gateway
+ */
+pub fn set_shard_manager(shard_manager: Arc<serenity::prelude::Mutex<ShardManager>>) {
+    STATE.lock().unwrap().shard_manager = Some(shard_manager);
+}
+
+/**
+ * Check each component: Discord gateway, D-Bus listener, and for each bridge
+ * the registration of the account and its RORIs (trusted contacts).
+ * Only the gateway and the listener decide the liveness, a restart doesn't fix the others
+ * @return (healthy, JSON report)
+ */
+pub async fn liveness() -> (bool, String) {
+    let (shard_manager, listening, heartbeat, bridges) = {
+        let state = STATE.lock().unwrap();
+        (state.shard_manager.clone(), state.listening, state.heartbeat, state.bridges.clone())
+    };
+    let mut components = Map::new();
+
+    let gateway = match shard_manager {
+        Some(shard_manager) => {
+            let manager = shard_manager.lock().await;
+            let runners = manager.runners.lock().await;
+            let connected = runners.values().filter(|runner| runner.stage == ConnectionStage::Connected).count();
+            (connected > 0 && connected == runners.len(), format!("{}/{} shard(s) connected", connected, runners.len()))
+        },
+        None => (false, String::from("not started")),
+    };
+
+    let listener = match heartbeat {
+        Some(heartbeat) if listening && heartbeat.elapsed() < LISTENER_TIMEOUT => (true, String::new()),
+        Some(_) if listening => (false, String::from("stuck")),
+        _ => (false, String::from("not listening")),
+    };
+    let healthy = gateway.0 && listener.0;
+    components.insert(String::from("discord_gateway"), component(gateway));
+    components.insert(String::from("dbus_listener"), component(listener));
+
+    for (account, bridge) in bridges {
+        components.insert(format!("{}/registered", account),
+            component((bridge.registration == "REGISTERED", bridge.registration.clone())));
+        components.insert(format!("{}/rori", account), component(roris_reachable(&bridge.roris, &bridge.contacts)));
+    }
+
+    (healthy, json!({ "healthy": healthy, "components": components }).to_string())
+}
+
+/**
+ * Ready once the Discord client is ready and the Ring accounts are loaded, until the bot stops
+ * @return (ready, JSON report)
+ */
+pub fn readiness() -> (bool, String) {
+    let state = STATE.lock().unwrap();
+    let waiting: Vec<&String> = state.bridges.iter().filter(|(_, bridge)| !bridge.loaded).map(|(account, _)| account).collect();
+    let ready = state.discord_ready && waiting.is_empty() && !state.shutting_down;
+    (ready, json!({
+        "ready": ready,
+        "discord_ready": state.discord_ready,
+        "accounts_not_loaded": waiting,
+        "shutting_down": state.shutting_down,
+    }).to_string())
+}
+
+fn component((ok, detail): (bool, String)) -> Value {
+    json!({ "ok": ok, "detail": detail })
+}
+
+/**
+ * @param roris ring ids of the RORIs
+ * @param contacts ring ids of the trusted contacts of the account, updated by its endpoint
+ * @return if all RORIs are trusted contacts of the account, and the untrusted ones
+ */
+fn roris_reachable(roris: &[String], contacts: &Result<Vec<String>, String>) -> (bool, String) {
+    let contacts = match contacts {
+        Ok(contacts) => contacts,
+        Err(why) => return (false, why.clone()),
+    };
+    let unreachable: Vec<&String> = roris.iter()
+        .filter(|rori| !contacts.contains(&rori.replace("ring:", "").to_lowercase()))
+        .collect();
+    if unreachable.is_empty() {
+        (true, String::new())
+    } else {
+        (false, format!("not a trusted contact: {:?}", unreachable))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roris_are_trusted_contacts() {
+        let contacts = Ok(vec![String::from("abcdef"), String::from("012345")]);
+        assert_eq!(roris_reachable(&[String::from("ring:ABCDEF"), String::from("012345")], &contacts), (true, String::new()));
+        let (reachable, detail) = roris_reachable(&[String::from("abcdef"), String::from("fedcba")], &contacts);
+        assert!(!reachable);
+        assert!(detail.contains("fedcba"));
+    }
+
+    #[test]
+    fn roris_unknown_without_contacts() {
+        let contacts = Err(String::from("getContacts fails"));
+        assert_eq!(roris_reachable(&[String::from("abcdef")], &contacts), (false, String::from("getContacts fails")));
+    }
+}
@@ -0,0 +1,78 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use crate::health;
+use crate::metrics;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+/**
+ * Serve the local HTTP endpoint: GET /metrics, /health and /ready
+ * @param addr to listen
+ */
+pub async fn serve(addr: SocketAddr) {
+    let server = match Server::try_bind(&addr) {
+        Ok(server) => server,
+        Err(why) => {
+            error!("Can't listen {}: {}", addr, why);
+            return;
+        }
+    };
+    info!("Metrics and health on http://{}/ (metrics, health, ready)", addr);
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(handle))
+    });
+    if let Err(why) = server.serve(make_service).await {
+        error!("HTTP server error: {}", why);
+    }
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            let (content_type, metrics) = metrics::gather();
+            Response::builder().header(CONTENT_TYPE, content_type).body(Body::from(metrics))
+        },
+        (&Method::GET, "/health") => json_response(health::liveness().await),
+        (&Method::GET, "/ready") => json_response(health::readiness()),
+        _ => Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()),
+    };
+    Ok(response.unwrap())
+}
+
+/**
+ * @param (ok, report) from health
+ * @return 200 with the report if ok, else 503
+ */
+fn json_response((ok, report): (bool, String)) -> Result<Response<Body>, hyper::http::Error> {
+    let status = if ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    Response::builder().status(status).header(CONTENT_TYPE, "application/json").body(Body::from(report))
+}
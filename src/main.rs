@@ -1,4 +1,4 @@
-/**
+/*
  * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
  * All rights reserved.
  * Redistribution and use in source and binary forms, with or without
@@ -25,195 +25,284 @@
  * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  **/
 
-extern crate dbus;
+extern crate clap;
 extern crate env_logger;
+extern crate futures;
 #[macro_use]
 extern crate log;
-extern crate reqwest;
-extern crate serenity;
-extern crate serde;
-extern crate serde_json;
-#[macro_use]
-extern crate serde_derive;
-extern crate time;
-
-
-pub mod discord;
-pub mod rori;
+extern crate rori_discord_bot;
 
-use discord::Bot;
-use discord::DiscordMsg;
-use rori::endpoint::Endpoint;
-use serde_json::{Value, from_str};
-use std::io::prelude::*;
-use std::io::{stdin,stdout,Write};
-use std::fs::File;
+use clap::{App, Arg, SubCommand};
+use futures::future::join_all;
+use rori_discord_bot::{accounts, doctor, health, http, setup};
+use rori_discord_bot::config::ConfigFile;
+use rori_discord_bot::discord::{Bot, BotEvent, Route};
+use rori_discord_bot::reload::{resolve_overrides, Reloader};
+use rori_discord_bot::rori::daemon::DaemonBus;
+use rori_discord_bot::rori::endpoint::Endpoint;
+use rori_discord_bot::rori::nameserver::NameServer;
 use std::path::Path;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
-use tokio;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::unbounded_channel;
 
-/**
- * Generate a config file
- */
+#[tokio::main]
+async fn main() {
+    // 0. Init logging
+    env_logger::init();
 
-#[derive(Serialize, Deserialize)]
-pub struct ConfigFile {
-    discord_secret_token: String,
-    ring_id: String,
-    rori_server: String,
-    rori_ring_id: String
-}
+    let matches = App::new("rori_discord_bot")
+        .about("Discord client for RORI")
+        .arg(Arg::with_name("config")
+            .short("c")
+            .long("config")
+            .value_name("FILE")
+            .env("RORI_BOT_CONFIG")
+            .default_value("config.json")
+            .global(true)
+            .help("Config file (JSON, or TOML with a .toml extension)"))
+        .arg(Arg::with_name("daemon")
+            .long("daemon")
+            .value_name("DAEMON")
+            .env("RORI_BOT_DAEMON")
+            .possible_values(&["auto", "ring", "jami"])
+            .global(true)
+            .help("D-Bus namespace of the daemon: ring (cx.ring.Ring), jami (net.jami.daemon) or auto"))
+        .subcommand(SubCommand::with_name("setup")
+            .about("Generate the config file")
+            .arg(Arg::with_name("interactive")
+                .short("i")
+                .long("interactive")
+                .conflicts_with_all(&["rori-server", "create-account", "import-archive", "account", "ca-file", "insecure",
+                                      "register-name"])
+                .help("Ask answers on stdin"))
+            .arg(Arg::with_name("force")
+                .short("f")
+                .long("force")
+                .help("Overwrite the config file if it exists"))
+            .arg(Arg::with_name("token")
+                .long("token")
+                .value_name("TOKEN")
+                .env("RORI_BOT_DISCORD_SECRET_TOKEN")
+                .hide_env_values(true)
+                .required_unless("interactive")
+                .help("Discord secret token"))
+            .arg(Arg::with_name("rori-server")
+                .long("rori-server")
+                .value_name("URL")
+                .required_unless("interactive")
+                .help("Name server of RORI"))
+            .arg(Arg::with_name("rori-name")
+                .long("rori-name")
+                .value_name("NAME")
+                .default_value("rori")
+                .help("Name of RORI on the name server"))
+            .arg(Arg::with_name("ca-file")
+                .long("ca-file")
+                .value_name("FILE")
+                .help("PEM file with the CA (or the self-signed certificate) of the name server"))
+            .arg(Arg::with_name("insecure")
+                .long("insecure")
+                .conflicts_with("ca-file")
+                .help("Don't verify the certificate of the name server"))
+            .arg(Arg::with_name("create-account")
+                .long("create-account")
+                .value_name("ALIAS")
+                .conflicts_with_all(&["import-archive", "account"])
+                .help("Create a new Ring account"))
+            .arg(Arg::with_name("import-archive")
+                .long("import-archive")
+                .value_name("PATH")
+                .conflicts_with("account")
+                .help("Import a Ring account from an archive"))
+            .arg(Arg::with_name("password")
+                .long("password")
+                .value_name("PASSWORD")
+                .env("RORI_BOT_ACCOUNT_PASSWORD")
+                .hide_env_values(true)
+                .help("Password of the created or imported account"))
+            .arg(Arg::with_name("register-name")
+                .long("register-name")
+                .value_name("NAME")
+                .help("Public name to register for the account"))
+            .arg(Arg::with_name("account")
+                .long("account")
+                .value_name("ACCOUNT")
+                .help("Existing account to use, by alias, Ring id or account id")))
+        .subcommand(accounts::subcommand())
+        .subcommand(SubCommand::with_name("check-config")
+            .alias("doctor")
+            .about("Check the config file, the daemon, the Ring accounts, the RORIs and the Discord token"))
+        .get_matches();
 
-fn clean_string(string: String) -> String {
-    let mut s = string.clone();
-    if let Some('\n') = s.chars().next_back() {
-        s.pop();
-    }
-    if let Some('\r') = s.chars().next_back() {
-        s.pop();
+    let config_path = Path::new(matches.value_of("config").unwrap_or("config.json"));
+    // Choose the daemon: --daemon, else the config file, else detect it
+    let daemon = match matches.value_of("daemon") {
+        Some(daemon) => String::from(daemon),
+        None => ConfigFile::load(config_path).map(|config| config.daemon).unwrap_or_default(),
+    };
+    if let Err(why) = DaemonBus::select(&daemon).await {
+        error!("{}", why);
+        std::process::exit(1);
     }
-    s
-}
-
-fn create_config_file() {
-    println!("Config file not found. Please answer to following questions:");
-    let mut s = String::new();
-    print!("Discord secret token: ");
-    let _ = stdout().flush();
-    stdin().read_line(&mut s).expect("Did not enter a correct string");
-    s = clean_string(s);
-    let discord_secret_token = s.clone();
-
-    let mut s = String::new();
-    print!("RORI server: ");
-    let _ = stdout().flush();
-    stdin().read_line(&mut s).expect("Did not enter a correct string");
-    s = clean_string(s);
-    let rori_server = s.clone();
-    let rori_ring_id = Endpoint::get_ring_id(&rori_server, &String::from("rori"));
-    if rori_ring_id == "" {
-        error!("Cannot connect to this RORI. Abort");
+    if let Some(setup_matches) = matches.subcommand_matches("setup") {
+        if config_path.exists() && !setup_matches.is_present("force") {
+            error!("{} already exists. Use --force to overwrite it", config_path.display());
+            std::process::exit(1);
+        }
+        let answers = if setup_matches.is_present("interactive") {
+            setup::answers_from_stdin(matches.value_of("daemon").unwrap_or("auto")).await
+        } else {
+            setup::answers_from_args(setup_matches)
+        };
+        if let Err(why) = setup::setup(answers, config_path).await {
+            error!("{}", why);
+            std::process::exit(1);
+        }
         return;
     }
 
-    println!("Create an account? y/N: ");
-    let _ = stdout().flush();
-    let mut s = String::new();
-    stdin().read_line(&mut s).expect("Did not enter a correct string");
-    s = clean_string(s);
-    if s.len() == 0 {
-        s = String::from("N");
-    }
-    if s.to_lowercase() == "y" {
-        let mut s = String::new();
-        println!("Import archive? y/N: ");
-        let _ = stdout().flush();
-        stdin().read_line(&mut s).expect("Did not enter a correct string");
-        s = clean_string(s);
-        if s.len() == 0 {
-            s = String::from("N");
+    if let Some(account_matches) = matches.subcommand_matches("account") {
+        if let Err(why) = accounts::run(account_matches, config_path).await {
+            error!("{}", why);
+            std::process::exit(1);
         }
-        let from_archive = s.to_lowercase() == "y";
-        if from_archive {
-            println!("path: ");
-        } else {
-            println!("alias: ");
+        return;
+    }
+    if matches.subcommand_matches("check-config").is_some() {
+        if !doctor::check_config(config_path).await {
+            std::process::exit(1);
         }
-        let _ = stdout().flush();
-        let mut s = String::new();
-        stdin().read_line(&mut s).expect("Did not enter a correct string");
-        s = clean_string(s);
-        let main_info = s.clone();
-        println!("password (optional):");
-        let _ = stdout().flush();
-        let mut s = String::new();
-        stdin().read_line(&mut s).expect("Did not enter a correct string");
-        s = clean_string(s);
-        let password = s.clone();
-        Endpoint::add_account(&*main_info, &*password, from_archive);
-        // Let some time for the daemon
-        let three_secs = Duration::from_millis(3000);
-        thread::sleep(three_secs);
+        return;
     }
 
-    let accounts = Endpoint::get_account_list();
-    let mut idx = 0;
-    println!("Choose an account:");
-    for account in &accounts {
-        println!("{}. {}", idx, account);
-        idx += 1;
-    }
-    println!("Your choice:");
-    let _ = stdout().flush();
-    let mut s = String::new();
-    stdin().read_line(&mut s).expect("Did not enter a correct string");
-    s = clean_string(s);
-    if s.len() == 0 {
-        s = String::from("0");
+    // 1. Read current config
+    if !config_path.exists() {
+        error!("{} not found. Please run `rori_discord_bot setup`", config_path.display());
+        std::process::exit(1);
     }
-    let s = s.parse::<usize>().unwrap_or(0);
-    if s >= accounts.len() {
+    let config = match ConfigFile::load(config_path) {
+        Ok(config) => config,
+        Err(why) => {
+            error!("{}", why);
+            return;
+        }
+    };
+    if config.discord_secret_token.is_empty() {
+        error!("No Discord token. Please check {} or set RORI_BOT_DISCORD_SECRET_TOKEN", config_path.display());
         return;
     }
-    let account = &accounts.get(s).unwrap().id;
-    let config = ConfigFile {
-        discord_secret_token,
-        ring_id: account.clone(),
-        rori_server,
-        rori_ring_id
+
+    // 2. Init Ring accounts, one per bridge
+    let name_server = match NameServer::new(&config.tls, &config.name_server) {
+        Ok(name_server) => name_server,
+        Err(why) => {
+            error!("{}", why);
+            std::process::exit(1);
+        }
     };
-    let config = serde_json::to_string_pretty(&config).unwrap_or(String::new());
-    let mut file = File::create("config.json").ok().expect("config.json found.");
-    let _ = file.write_all(config.as_bytes());
+    if !config.http_listen.is_empty() {
+        match config.http_listen.parse() {
+            Ok(addr) => {
+                tokio::spawn(http::serve(addr));
+            },
+            Err(why) => error!("Incorrect http_listen {}: {}", config.http_listen, why),
+        }
+    }
+    let mut reloader = Reloader::new(config_path.to_path_buf(), config.clone());
+    let mut routes = Vec::new();
+    let (rori_say, rori_said) = unbounded_channel();
+    let mut bridges = Vec::new();
+    for bridge in config.get_bridges() {
+        let (user_say, user_text) = unbounded_channel();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        routes.push(Route {
+            name: bridge.ring_id.clone(),
+            guilds: bridge.guilds.iter().filter_map(|guild| guild.parse::<u64>().ok()).collect(),
+            user_say,
+            in_flight: in_flight.clone(),
+        });
 
-}
+        health::add_bridge(&bridge.ring_id);
+        let rori_overrides = resolve_overrides(&name_server, &bridge.overrides).await;
+        bridges.push(async move {
+            match Endpoint::init(&bridge.ring_id, &bridge.rori_ring_id, rori_overrides).await {
+                Ok(endpoint) => Some((bridge.ring_id, endpoint, user_text, in_flight)),
+                Err(why) => {
+                    error!("Can't initialize ConfigurationEndpoint for {}: {}", bridge.ring_id, why);
+                    None
+                }
+            }
+        });
+    }
+    // Register the endpoints before listening to the daemon, else the first messages of RORI are dropped
+    let endpoints = reloader.endpoints();
+    for (ring_id, endpoint, user_text, in_flight) in join_all(bridges).await.into_iter().flatten() {
+        let shared_endpoint = Arc::new(tokio::sync::Mutex::new(endpoint));
+        endpoints.lock().unwrap().insert(ring_id, shared_endpoint.clone());
+        tokio::spawn(Endpoint::forward_messages(shared_endpoint, user_text, in_flight));
+    }
+    // One listener for the signals of the daemon, routed to the bridge of the account
+    tokio::spawn(Endpoint::listen_signals(endpoints, rori_say));
 
-#[tokio::main]
-async fn main() {
-    // 0. Init logging
-    env_logger::init();
+    // 3. Run discord bot
+    let mut bot = Bot::new(&config.discord_secret_token, config.bot_options(), routes, rori_said, name_server);
+    let mut client = bot.run().await;
 
-    // 1. Read current config
-    // but if no config, create it
-    if !Path::new("config.json").exists() {
-        create_config_file();
+    // Reload the configuration on SIGHUP
+    match signal(SignalKind::hangup()) {
+        Ok(mut hangups) => {
+            let reload_sender = bot.reload_sender();
+            tokio::spawn(async move {
+                while hangups.recv().await.is_some() {
+                    let _ = reload_sender.send(None);
+                }
+            });
+        },
+        Err(why) => error!("Can't listen SIGHUP: {}", why),
     }
-    let mut file = File::open("config.json").ok()
-            .expect("Config file not found");
-    let mut config = String::new();
-    file.read_to_string(&mut config).ok()
-        .expect("failed to read!");
-    let config: Value = from_str(&*config).ok()
-                        .expect("Incorrect config file. Please check config.json");
-    let config_cloned = config.clone();
-
-    // 2. Init Ring account
-    let user_text = Arc::new(Mutex::new(DiscordMsg::new()));
-    let rori_text = Arc::new(Mutex::new(DiscordMsg::new()));
-    let user_text_cloned = user_text.clone();
-    let rori_text_cloned = rori_text.clone();
 
-    let _handle_signals = thread::spawn(move || {
-        let shared_endpoint : Arc<Mutex<Endpoint>> = Arc::new(Mutex::new(
-            Endpoint::init(config["ring_id"].as_str().unwrap_or(""),
-                           config["rori_ring_id"].as_str().unwrap_or(""))
-            .ok().expect("Can't initialize ConfigurationEndpoint"))
-        );
-        Endpoint::handle_signals(shared_endpoint, user_text, rori_text);
-    });
+    // Shut down gracefully on SIGINT/SIGTERM, at once on the second one
+    let (shutdown_sender, mut shutdowns) = unbounded_channel();
+    match (signal(SignalKind::interrupt()), signal(SignalKind::terminate())) {
+        (Ok(mut interrupts), Ok(mut terminates)) => {
+            tokio::spawn(async move {
+                let mut shutting_down = false;
+                loop {
+                    tokio::select! {
+                        _ = interrupts.recv() => {},
+                        _ = terminates.recv() => {},
+                    }
+                    if shutting_down {
+                        warn!("Stop now");
+                        std::process::exit(1);
+                    }
+                    shutting_down = true;
+                    let _ = shutdown_sender.send(());
+                }
+            });
+        },
+        (Err(why), _) | (_, Err(why)) => error!("Can't listen SIGINT/SIGTERM: {}", why),
+    }
 
-    // 3. Run discord bot
-    let mut bot = Bot::new(&config_cloned["discord_secret_token"].as_str().unwrap_or(""));
-    let mut client = bot.run(user_text_cloned).await;
+    let shard_manager = client.shard_manager.clone();
+    health::set_shard_manager(shard_manager.clone());
     tokio::spawn(async move {
-        let five_hundred_ms = Duration::from_millis(500);
         loop {
-            bot.handle_messages(&rori_text_cloned).await;
-            // Let some time for the daemon
-            tokio::time::delay_for(five_hundred_ms).await;
+            tokio::select! {
+                event = bot.next_event() => match event {
+                    BotEvent::Reload(channel) => {
+                        let report = reloader.reload(&mut bot).await;
+                        info!("{}", report);
+                        bot.report(channel.into_iter().collect(), &report).await;
+                    },
+                    event => bot.handle_event(event).await,
+                },
+                Some(()) = shutdowns.recv() => break,
+            }
         }
+        bot.shutdown(shard_manager).await;
     });
     if let Err(why) = client.start().await {
         error!("Client error: {:?}", why);
@@ -0,0 +1,94 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use prometheus::{Encoder, Histogram, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder};
+
+/**
+ * States of Account.registrationStatus, to reset the previous state of an account
+ */
+const REGISTRATION_STATES: [&str; 10] = ["UNREGISTERED", "TRYING", "REGISTERED", "ERROR_GENERIC", "ERROR_AUTH",
+    "ERROR_NETWORK", "ERROR_HOST", "ERROR_SERVICE_UNAVAILABLE", "ERROR_NEED_MIGRATION", "INITIALIZING"];
+
+lazy_static! {
+    // direction: to_rori or from_rori. guild: guild id, dm, or unknown
+    pub static ref MESSAGES: IntCounterVec = register_int_counter_vec!(
+        "rori_bot_messages_total", "Messages forwarded between Discord and RORI", &["direction", "guild"]).unwrap();
+    // result: forwarded, or throttled_user, throttled_channel, throttled_guild for the limit reached
+    pub static ref RATE_LIMIT: IntCounterVec = register_int_counter_vec!(
+        "rori_bot_rate_limit_total", "Messages checked by the rate limiter", &["result"]).unwrap();
+    pub static ref RORI_REPLY_SECONDS: Histogram = register_histogram!(
+        "rori_bot_rori_reply_seconds", "Time for RORI to answer a Discord message",
+        vec![0.1, 0.25, 0.5, 1., 2.5, 5., 10., 30., 60.]).unwrap();
+    pub static ref DBUS_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "rori_bot_dbus_errors_total", "Failed calls to the daemon", &["method"]).unwrap();
+    pub static ref DISCORD_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "rori_bot_discord_errors_total", "Failed calls to the Discord API", &["action"]).unwrap();
+    // direction: to_rori (from Discord) or from_rori (to Discord)
+    pub static ref QUEUE_DEPTH: IntGaugeVec = register_int_gauge_vec!(
+        "rori_bot_queue_depth", "Messages waiting in the queues of each bridge", &["bridge", "direction"]).unwrap();
+    // 1 for the current state of the account
+    pub static ref REGISTRATION_STATE: IntGaugeVec = register_int_gauge_vec!(
+        "rori_bot_registration_state", "Registration state of the Ring accounts", &["account", "state"]).unwrap();
+    pub static ref NAME_SERVER_SECONDS: HistogramVec = register_histogram_vec!(
+        "rori_bot_name_server_lookup_seconds", "Time of name server requests, cache misses only", &["result"]).unwrap();
+}
+
+/**
+ * @param guild of a message (None for direct messages)
+ * @return the guild label of the metrics
+ */
+pub fn guild_label(guild: Option<Option<u64>>) -> String {
+    match guild {
+        Some(Some(guild)) => guild.to_string(),
+        Some(None) => String::from("dm"),
+        None => String::from("unknown"),
+    }
+}
+
+/**
+ * @param account id
+ * @param state new Account.registrationStatus
+ */
+pub fn set_registration_state(account: &str, state: &str) {
+    for known in REGISTRATION_STATES.iter().filter(|known| **known != state) {
+        REGISTRATION_STATE.with_label_values(&[account, known]).set(0);
+    }
+    REGISTRATION_STATE.with_label_values(&[account, state]).set(1);
+}
+
+/**
+ * @return the content type and all metrics, in the Prometheus text format
+ */
+pub fn gather() -> (String, Vec<u8>) {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(why) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        error!("Can't encode metrics: {}", why);
+    }
+    (String::from(encoder.format_type()), buffer)
+}
@@ -0,0 +1,156 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use crate::config::{ConfigFile, RoriOverride};
+use crate::discord::Bot;
+use crate::rori::endpoint::Endpoints;
+use crate::rori::nameserver::NameServer;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/**
+ * Re-read the config file and apply what can be applied without a restart
+ */
+pub struct Reloader {
+    config_path: PathBuf,
+    // Config used to start the bot
+    started: ConfigFile,
+    // Last config applied
+    applied: ConfigFile,
+    endpoints: Endpoints,
+}
+
+/**
+ * Resolve the RORIs of the overrides
+ * @param name_server
+ * @param overrides
+ * @return "guild" or "guild/channel" -> ring id of the RORI
+ */
+pub async fn resolve_overrides(name_server: &NameServer, overrides: &[RoriOverride]) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+    for rori_override in overrides {
+        let rori_ring_id = name_server.get_ring_id(&rori_override.rori_server, &rori_override.rori_name).await;
+        if rori_ring_id.is_empty() {
+            error!("Cannot resolve {} on {}. Ignore override for {}", rori_override.rori_name,
+                   rori_override.rori_server, rori_override.guild);
+            continue;
+        }
+        let key = if rori_override.channel.is_empty() {
+            rori_override.guild.clone()
+        } else {
+            format!("{}/{}", rori_override.guild, rori_override.channel)
+        };
+        info!("{} handled by {} ({})", key, rori_override.rori_name, rori_ring_id);
+        result.insert(key, rori_ring_id);
+    }
+    result
+}
+
+impl Reloader {
+    /**
+     * @param config_path
+     * @param config used to start the bot
+     */
+    pub fn new(config_path: PathBuf, config: ConfigFile) -> Reloader {
+        Reloader {
+            config_path,
+            started: config.clone(),
+            applied: config,
+            endpoints: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /**
+     * @param self
+     * @return the endpoints to update on reload. Each bridge registers its endpoint when ready
+     */
+    pub fn endpoints(&self) -> Endpoints {
+        self.endpoints.clone()
+    }
+
+    /**
+     * Re-read the config file and apply live changes
+     * @param self
+     * @param bot
+     * @return a report of the changes
+     */
+    pub async fn reload(&mut self, bot: &mut Bot) -> String {
+        let config = match ConfigFile::load(&self.config_path) {
+            Ok(config) => config,
+            Err(why) => return format!("Configuration not reloaded: {}", why),
+        };
+        let problems = config.check();
+        if !problems.is_empty() {
+            return format!("Configuration not reloaded: {}", problems.join(", "));
+        }
+        let name_server = match NameServer::new(&config.tls, &config.name_server) {
+            Ok(name_server) => name_server,
+            Err(why) => return format!("Configuration not reloaded: {}", why),
+        };
+        let (live, _) = self.applied.diff(&config);
+        let (_, restart) = self.started.diff(&config);
+
+        // Bot
+        let mut guilds = HashMap::new();
+        for bridge in config.get_bridges() {
+            guilds.insert(bridge.ring_id.clone(),
+                          bridge.guilds.iter().filter_map(|guild| guild.parse::<u64>().ok()).collect());
+        }
+        bot.reload(config.bot_options(), guilds, name_server.clone());
+
+        // RORIs of the endpoints
+        let old_bridges = self.applied.get_bridges();
+        for bridge in config.get_bridges() {
+            let unchanged = old_bridges.iter().any(|old| old.ring_id == bridge.ring_id
+                && old.rori_ring_id == bridge.rori_ring_id && old.overrides == bridge.overrides)
+                && self.applied.tls == config.tls && self.applied.name_server == config.name_server;
+            if unchanged {
+                continue;
+            }
+            let endpoint = match self.endpoints.lock().unwrap().get(&bridge.ring_id) {
+                Some(endpoint) => endpoint.clone(),
+                None => continue,
+            };
+            let rori_overrides = resolve_overrides(&name_server, &bridge.overrides).await;
+            endpoint.lock().await.set_rori(&bridge.rori_ring_id, rori_overrides);
+        }
+        self.applied = config;
+
+        let mut report = String::from("Configuration reloaded.");
+        if live.is_empty() {
+            report += " Nothing to apply.";
+        } else {
+            report += &*format!(" Applied: {}.", live.join(", "));
+        }
+        if !restart.is_empty() {
+            report += &*format!(" Needs a restart: {}.", restart.join(", "));
+        }
+        report
+    }
+}
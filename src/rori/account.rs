@@ -1,4 +1,4 @@
-/**
+/*
  * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
  * All rights reserved.
  * Redistribution and use in source and binary forms, with or without
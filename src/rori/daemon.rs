@@ -0,0 +1,244 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use dbus::message::MatchRule;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus_tokio::connection;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio;
+
+/**
+ * Timeout of method calls
+ */
+pub const DBUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/**
+ * Bus name and objects of a daemon. Old daemons are named Ring, current ones Jami
+ */
+#[derive(Debug, PartialEq)]
+pub struct DaemonBus {
+    pub kind: &'static str,
+    pub name: &'static str,
+    pub configuration_path: &'static str,
+    pub configuration_iface: &'static str,
+}
+
+pub const RING: DaemonBus = DaemonBus {
+    kind: "ring",
+    name: "cx.ring.Ring",
+    configuration_path: "/cx/ring/Ring/ConfigurationManager",
+    configuration_iface: "cx.ring.Ring.ConfigurationManager",
+};
+
+pub const JAMI: DaemonBus = DaemonBus {
+    kind: "jami",
+    name: "net.jami.daemon",
+    configuration_path: "/net/jami/daemon/ConfigurationManager",
+    configuration_iface: "net.jami.daemon.ConfigurationManager",
+};
+
+// 0: not selected yet, 1: RING, 2: JAMI
+static SELECTED: AtomicUsize = AtomicUsize::new(0);
+
+/**
+ * Call a method of the ConfigurationManager of a daemon with the generated proxy
+ * (see configurationmanager.rs), e.g. configuration_manager!(bus, &conn, get_account_list())
+ * @return the answer, or an error to show
+ */
+macro_rules! configuration_manager {
+    ($bus:expr, $conn:expr, $method:ident($($arg:expr),*)) => {
+        configuration_manager!($bus, $conn, $crate::rori::daemon::DBUS_TIMEOUT, $method($($arg),*))
+    };
+    ($bus:expr, $conn:expr, $timeout:expr, $method:ident($($arg:expr),*)) => {{
+        let bus: &$crate::rori::daemon::DaemonBus = $bus;
+        let proxy = bus.proxy($conn, $timeout);
+        let reply = if bus.kind == $crate::rori::daemon::JAMI.kind {
+            $crate::rori::configurationmanager::jami::ConfigurationManager::$method(&proxy, $($arg),*).await
+        } else {
+            $crate::rori::configurationmanager::ring::ConfigurationManager::$method(&proxy, $($arg),*).await
+        };
+        reply.map_err(|e| {
+            $crate::metrics::DBUS_ERRORS.with_label_values(&[stringify!($method)]).inc();
+            format!("{} fails: {}", stringify!($method), e)
+        })
+    }};
+}
+
+lazy_static! {
+    static ref SESSION: DaemonConnection = DaemonConnection::new();
+}
+
+/**
+ * @return the connection to the session bus shared by the process
+ */
+pub fn connect() -> Result<Arc<SyncConnection>, String> {
+    SESSION.get()
+}
+
+/**
+ * Long-lived connection to the session bus, driven by the tokio runtime.
+ * Opened on first use and opened again once lost
+ */
+#[derive(Clone)]
+pub struct DaemonConnection {
+    conn: Arc<Mutex<Option<Arc<SyncConnection>>>>,
+}
+
+impl DaemonConnection {
+    fn new() -> DaemonConnection {
+        DaemonConnection {
+            conn: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /**
+     * @return the connection shared by the process
+     */
+    pub fn shared() -> DaemonConnection {
+        SESSION.clone()
+    }
+
+    /**
+     * @param self
+     * @return the current connection, a new one if the previous one was lost
+     */
+    pub fn get(&self) -> Result<Arc<SyncConnection>, String> {
+        let mut current = self.conn.lock().unwrap();
+        if let Some(conn) = &*current {
+            return Ok(conn.clone());
+        }
+        let (resource, conn) = connection::new_session_sync()
+            .map_err(|e| format!("Can't connect to the session bus: {}", e))?;
+        let shared = self.conn.clone();
+        let lost = conn.clone();
+        tokio::spawn(async move {
+            let err = resource.await;
+            error!("Lost connection to the session bus: {}", err);
+            let mut current = shared.lock().unwrap();
+            if current.as_ref().map(|conn| Arc::ptr_eq(conn, &lost)).unwrap_or(false) {
+                *current = None;
+            }
+        });
+        debug!("Connected to the session bus as {}", conn.unique_name());
+        *current = Some(conn.clone());
+        Ok(conn)
+    }
+
+    /**
+     * @param self
+     * @param conn a connection given by get()
+     * @return if conn is still the current connection
+     */
+    pub fn is_current(&self, conn: &Arc<SyncConnection>) -> bool {
+        self.conn.lock().unwrap().as_ref().map(|current| Arc::ptr_eq(current, conn)).unwrap_or(false)
+    }
+}
+
+impl DaemonBus {
+    /**
+     * Choose the daemon used by every call and signal
+     * @param kind "ring", "jami" or "auto" to detect it
+     * @return the daemon, or an error to show
+     */
+    pub async fn select(kind: &str) -> Result<&'static DaemonBus, String> {
+        let bus = match kind {
+            "ring" => &RING,
+            "jami" => &JAMI,
+            "auto" | "" => DaemonBus::detect().await,
+            _ => return Err(format!("Unknown daemon {}. Use auto, ring or jami", kind)),
+        };
+        SELECTED.store(if bus == &JAMI { 2 } else { 1 }, Ordering::SeqCst);
+        info!("Using the {} daemon ({})", bus.kind, bus.name);
+        Ok(bus)
+    }
+
+    /**
+     * @return the selected daemon (Ring if not selected yet)
+     */
+    pub fn current() -> &'static DaemonBus {
+        match SELECTED.load(Ordering::SeqCst) {
+            2 => &JAMI,
+            _ => &RING,
+        }
+    }
+
+    /**
+     * Detect the daemon on the session bus: running first, then activatable. Ring if none is found
+     * @return the daemon
+     */
+    pub async fn detect() -> &'static DaemonBus {
+        let conn = match connect() {
+            Ok(conn) => conn,
+            Err(why) => {
+                warn!("{}, try {}", why, RING.name);
+                return &RING;
+            }
+        };
+        let names = DaemonBus::bus_names(&conn, "ListNames").await;
+        let names = if names.iter().any(|n| n == JAMI.name || n == RING.name) {
+            names
+        } else {
+            DaemonBus::bus_names(&conn, "ListActivatableNames").await
+        };
+        if names.iter().any(|n| n == JAMI.name) {
+            &JAMI
+        } else {
+            if !names.iter().any(|n| n == RING.name) {
+                warn!("No daemon found on the session bus, try {}", RING.name);
+            }
+            &RING
+        }
+    }
+
+    /**
+     * @param self
+     * @param member of the signal
+     * @return the rule to listen this signal
+     */
+    pub fn signal_rule(&self, member: &'static str) -> MatchRule<'static> {
+        MatchRule::new_signal(self.configuration_iface, member)
+    }
+
+    /**
+     * @param self
+     * @param conn
+     * @param timeout of the calls
+     * @return a proxy to the ConfigurationManager
+     */
+    pub fn proxy(&self, conn: &Arc<SyncConnection>, timeout: Duration) -> Proxy<'static, Arc<SyncConnection>> {
+        Proxy::new(self.name, self.configuration_path, timeout, conn.clone())
+    }
+
+    async fn bus_names(conn: &Arc<SyncConnection>, method: &str) -> Vec<String> {
+        let proxy = Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", DBUS_TIMEOUT, conn.clone());
+        let names: Result<(Vec<String>,), _> = proxy.method_call("org.freedesktop.DBus", method, ()).await;
+        names.map(|(names,)| names).unwrap_or_default()
+    }
+}
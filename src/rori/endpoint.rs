@@ -1,4 +1,4 @@
-/**
+/*
  * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
  * All rights reserved.
  * Redistribution and use in source and binary forms, with or without
@@ -25,17 +25,36 @@
  * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  **/
 
-use dbus::{Connection, ConnectionItem, BusType, Message};
-use dbus::arg::{Array, Dict};
-use crate::discord::DiscordMsg;
-use reqwest;
+use dbus::Message;
+use dbus::nonblock::SyncConnection;
+use crate::discord::{DiscordMsg, RoriSay};
+use crate::health;
+use crate::metrics;
+use futures::stream::{self, StreamExt};
 use super::account::Account;
+use super::daemon::{connect, DaemonBus, DaemonConnection};
 use super::interaction::Interaction;
-use serde_json::{Value, from_str};
+use super::signals;
+use super::signals::{ContactAdded, ContactRemoved, IncomingAccountMessage, NameRegistrationEnded, RegistrationStateChanged, Signal};
 use std::collections::HashMap;
-use std::io::Read;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use time;
+use tokio;
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/**
+ * Datatypes RORI can send to the bridge
+ * discord/edit, discord/delete and discord/reaction target a previous reply via the "rid" metadata
+ */
+const SUPPORTED_DATATYPES: [&str; 4] = ["text/plain", "discord/edit", "discord/delete", "discord/reaction"];
+
+/**
+ * Ring account id -> endpoint of the bridge
+ */
+pub type Endpoints = Arc<std::sync::Mutex<HashMap<String, Arc<Mutex<Endpoint>>>>>;
 
 /**
  * This class is used to load RORI accounts and handle signals from Ring.
@@ -45,165 +64,215 @@ pub struct Endpoint {
     pub account: Account,
 
     rori_ring_id: String,
-    ring_dbus: &'static str,
-    configuration_path: &'static str,
-    configuration_iface: &'static str,
+    // "guild" or "guild/channel" -> ring id of the RORI to use instead of rori_ring_id
+    rori_overrides: HashMap<String, String>,
+    // channel -> (guild, ring id of the RORI which can answer in this channel)
+    channel_roris: HashMap<String, (String, String)>,
+    bus: &'static DaemonBus,
+    conn: DaemonConnection,
 }
 
 impl Endpoint {
     /**
      * Init the RORI server, the database and retrieve the RING account linked
      * @param ring_id to retrieve
+     * @param rori_ring_id the default RORI
+     * @param rori_overrides RORIs to use for some guilds ("guild") or channels ("guild/channel")
      * @return a Manager if success, else an error
      */
-    pub fn init(ring_id: &str, rori_ring_id: &str) -> Result<Endpoint, &'static str> {
+    pub async fn init(ring_id: &str, rori_ring_id: &str, rori_overrides: HashMap<String, String>) -> Result<Endpoint, String> {
         let mut manager = Endpoint {
             account: Account::null(),
 
             rori_ring_id: String::from(rori_ring_id),
-            ring_dbus: "cx.ring.Ring",
-            configuration_path: "/cx/ring/Ring/ConfigurationManager",
-            configuration_iface: "cx.ring.Ring.ConfigurationManager",
+            rori_overrides,
+            channel_roris: HashMap::new(),
+            bus: DaemonBus::current(),
+            conn: DaemonConnection::shared(),
         };
-        manager.account = Endpoint::build_account(ring_id);
+        manager.account = Endpoint::build_account(&manager.conn.get()?, ring_id).await;
         if !manager.account.enabled {
             info!("{} was not enabled. Enable it", ring_id);
-            manager.enable_account();
+            manager.enable_account().await;
         }
         debug!("Get: {}", manager.account.ring_id);
-        if manager.account.ring_id == "" {
-            return Err("Cannot build RORI account, please check configuration");
+        if manager.account.ring_id.is_empty() {
+            return Err(String::from("Cannot build RORI account, please check configuration"));
         }
         info!("{}: Account loaded", manager.account.id);
+        let conn = manager.conn.get()?;
+        if let Ok(details) = configuration_manager!(manager.bus, &conn, get_volatile_account_details(&*manager.account.id)) {
+            if let Some(state) = details.get("Account.registrationStatus") {
+                metrics::set_registration_state(&manager.account.id, state);
+                health::set_registration(&manager.account.id, state);
+            }
+        }
+        health::set_roris(&manager.account.id, manager.roris());
+        Endpoint::refresh_contacts(manager.account.id.clone()).await;
+        health::set_loaded(&manager.account.id);
         Ok(manager)
     }
 
     /**
-     * Listen from interresting signals from dbus and call handlers
+     * Change the RORIs to talk with
      * @param self
+     * @param rori_ring_id the default RORI
+     * @param rori_overrides RORIs to use for some guilds ("guild") or channels ("guild/channel")
      */
-    pub fn handle_signals(manager: Arc<Mutex<Endpoint>>, user_text: Arc<Mutex<DiscordMsg>>, rori_text: Arc<Mutex<DiscordMsg>>) {
-        // Use another dbus connection to listen signals.
-        let dbus_listener = Connection::get_private(BusType::Session).unwrap();
-        dbus_listener.add_match("interface=cx.ring.Ring.ConfigurationManager,member=incomingAccountMessage").unwrap();
-        dbus_listener.add_match("interface=cx.ring.Ring.ConfigurationManager,member=incomingTrustRequest").unwrap();
-        dbus_listener.add_match("interface=cx.ring.Ring.ConfigurationManager,member=accountsChanged").unwrap();
-        dbus_listener.add_match("interface=cx.ring.Ring.ConfigurationManager,member=registrationStateChanged").unwrap();
-        // For each signals, call handlers.
-        for i in dbus_listener.iter(100) {
-
-            let mut m = manager.lock().unwrap();
-            m.handle_accounts_signals(&i);
-            m.handle_registration_changed(&i);
-            if let Some((account_id, interaction)) = m.handle_interactions(&i) {
-                info!("New interation for {}: {}", account_id, interaction);
-                if account_id == m.account.id {
-                    // TODO forward all interaction.
-                    let mut channel = String::new();
-                    if interaction.metadatas.contains_key("ch") {
-                        channel = interaction.metadatas["ch"].clone();
-                    }
-                    *rori_text.lock().unwrap() = DiscordMsg {
-                        id: String::new(),
-                        body: interaction.body,
-                        author: String::new(),
-                        channel: channel,
-                    };
+    pub fn set_rori(&mut self, rori_ring_id: &str, rori_overrides: HashMap<String, String>) {
+        info!("{}: RORI is now {} with {} override(s)", self.account.id, rori_ring_id, rori_overrides.len());
+        self.rori_ring_id = String::from(rori_ring_id);
+        self.rori_overrides = rori_overrides;
+        // The RORI of a channel may have changed, answers from the previous one are ignored
+        let channels: Vec<(String, String)> = self.channel_roris.iter()
+            .map(|(channel, (guild, _))| (channel.clone(), guild.clone()))
+            .collect();
+        for (channel, guild) in channels {
+            let rori = self.get_rori(&guild, &channel);
+            self.channel_roris.insert(channel, (guild, rori));
+        }
+        health::set_roris(&self.account.id, self.roris());
+    }
+
+    /**
+     * @param self
+     * @return the ring ids of the default RORI and of the overrides
+     */
+    fn roris(&self) -> Vec<String> {
+        let mut roris = vec![self.rori_ring_id.clone()];
+        for rori in self.rori_overrides.values() {
+            if !roris.contains(rori) {
+                roris.push(rori.clone());
+            }
+        }
+        roris
+    }
+
+    /**
+     * Listen the signals of the daemon for all the endpoints, and give each signal to the
+     * endpoint of its account. The daemon only gives a signal to the first match of the connection,
+     * so only one listener must run in the process
+     * @param endpoints of the bridges, registered when ready
+     * @param rori_text where to send messages from RORI
+     */
+    pub async fn listen_signals(endpoints: Endpoints, rori_text: RoriSay) {
+        let shared = DaemonConnection::shared();
+        let bus = DaemonBus::current();
+        let mut check = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            // Listen signals on the current connection, until it is lost
+            let conn = match shared.get() {
+                Ok(conn) => conn,
+                Err(why) => {
+                    error!("{}. Retry in 5 seconds", why);
+                    tokio::time::delay_for(Duration::from_secs(5)).await;
+                    continue;
                 }
             };
-            if let Some((account_id, from)) = m.handle_requests(&i) {
-                if account_id == m.account.id {
-                    info!("New request from {}", from);
-                    // TODO
+            let mut matches = Vec::new();
+            let mut streams = Vec::new();
+            for signal in &signals::MEMBERS {
+                match conn.add_match(bus.signal_rule(signal)).await {
+                    Ok(msg_match) => {
+                        let (msg_match, stream) = msg_match.msg_stream();
+                        matches.push(msg_match);
+                        streams.push(stream);
+                    },
+                    Err(why) => error!("Can't listen {}: {}", signal, why),
                 }
-            };
-            let utext = user_text.lock().unwrap().clone();
-            if utext.body != "" {
-                *user_text.lock().unwrap() = DiscordMsg::new();
-                let mut datatype = "text/plain";
-                if m.is_a_command(&utext.body) {
-                    datatype = "rori/command";
+            }
+            if streams.len() != signals::MEMBERS.len() {
+                tokio::time::delay_for(Duration::from_secs(5)).await;
+                continue;
+            }
+            let mut signals = stream::select_all(streams);
+            health::set_listening(true);
+            loop {
+                tokio::select! {
+                    signal = signals.next() => {
+                        let signal = match signal {
+                            Some(msg) => Endpoint::decode_signal(bus, msg),
+                            None => break,
+                        };
+                        if let Some(signal) = signal {
+                            Endpoint::dispatch_signal(&endpoints, signal, &rori_text).await;
+                        }
+                    },
+                    _ = check.tick() => {
+                        if !shared.is_current(&conn) {
+                            break;
+                        }
+                        health::heartbeat();
+                    },
                 }
-                let mut payloads: HashMap<&str, &str> = HashMap::new();
-                payloads.insert(datatype, &*utext.body);
-                payloads.insert("sa", &*utext.author);
-                payloads.insert("th", &*utext.id);
-                payloads.insert("ch", &*utext.channel);
-                m.send_interaction_to_rori(payloads);
             }
+            health::set_listening(false);
+            warn!("Signals from the daemon lost, listen again");
         }
     }
 
-    pub fn get_ring_id(nameserver: &String, name: &String) -> String {
-        // NOTE/TODO: Remove this line when RORI will generate certificate with Let's Encrypt
-        // For now, self signed certificate and local dev, so it's OK
-        let client = reqwest::ClientBuilder::new()
-                    .danger_accept_invalid_certs(true)
-                    .build().unwrap();
-
-        let mut ns = nameserver.clone();
-        if ns.find("http") != Some(0) {
-            ns = String::from("https://") + &*ns;
-        }
-        let mut res = match client.get(&*format!("{}/name/{}", ns, name)).send() {
-            Ok(res) => res,
-            _ => {
-                return String::new();
+    /**
+     * A bad signal is skipped, the listener must keep running
+     * @param bus of the daemon
+     * @param msg the signal
+     * @return the decoded signal, if handled
+     */
+    fn decode_signal(bus: &DaemonBus, msg: Message) -> Option<Signal> {
+        match signals::decode(&msg, bus.configuration_iface) {
+            Ok(signal) => signal,
+            Err(why) => {
+                warn!("Skip signal from {}: {}", msg.sender().map(|s| s.to_string()).unwrap_or_default(), why);
+                None
             }
-        };
+        }
+    }
 
-        let mut body: String = String::new();
-        let _ = res.read_to_string(&mut body);
-        match from_str(&body) {
-            Ok(j) => {
-                // Only if rori order
-                let j: Value = j;
-                let addr = j["addr"].to_string();
-                if addr.len() > 4 {
-                    return String::from(&addr[3..addr.len()-1]);
+    /**
+     * Give a signal of the daemon to the endpoint of its account (to all endpoints for accountsChanged)
+     * @param endpoints of the bridges
+     * @param signal to give
+     * @param rori_text where to send messages from RORI
+     */
+    async fn dispatch_signal(endpoints: &Endpoints, signal: Signal, rori_text: &RoriSay) {
+        let account_id = match signal.account_id() {
+            Some(account_id) => String::from(account_id),
+            None => {
+                let all: Vec<Arc<Mutex<Endpoint>>> = endpoints.lock().unwrap().values().cloned().collect();
+                for endpoint in all {
+                    endpoint.lock().await.handle_accounts_signals();
                 }
-                return String::new();
-            },
-            _ => {
-                return String::new();
+                return;
             }
         };
+        let endpoint = endpoints.lock().unwrap().get(&account_id).cloned();
+        match endpoint {
+            Some(endpoint) => endpoint.lock().await.handle_signal(signal, rori_text),
+            None => debug!("Skip signal for {}, not bridged", account_id),
+        }
     }
 
-    pub fn get_username_from_api(nameserver: &String, ring_id: &String) -> String {
-        // NOTE/TODO: Remove this line when RORI will generate certificate with Let's Encrypt
-        // For now, self signed certificate and local dev, so it's OK
-        let client = reqwest::ClientBuilder::new()
-                    .danger_accept_invalid_certs(true)
-                    .build().unwrap();
-
-        let mut ns = nameserver.clone();
-        if ns.find("http") != Some(0) {
-            ns = String::from("https://") + &*ns;
-        }
-        let mut res = match client.get(&*format!("{}/addr/{}", ns, ring_id)).send() {
-            Ok(res) => res,
-            _ => {
-                return String::new();
-            }
+    /**
+     * Forward what users say to RORI, until the bot stops
+     * @param manager
+     * @param user_text messages from Discord
+     * @param in_flight counter of the route, decreased for each forwarded message
+     */
+    pub async fn forward_messages(manager: Arc<Mutex<Endpoint>>, mut user_text: UnboundedReceiver<DiscordMsg>,
+                                  in_flight: Arc<AtomicUsize>) {
+        let (shared, account_id) = {
+            let m = manager.lock().await;
+            (m.conn.clone(), m.account.id.clone())
         };
-
-        let mut body: String = String::new();
-        let _ = res.read_to_string(&mut body);
-        match from_str(&body) {
-            Ok(j) => {
-                // Only if rori order
-                let j: Value = j;
-                if j["name"].is_null() {
-                    return String::new();
-                } else {
-                    return j["name"].as_str().unwrap_or("").to_string();
-                }
-            },
-            _ => {
-                return String::new();
+        while let Some(utext) = user_text.recv().await {
+            // Messages from Discord wait in the queue while disconnected
+            while let Err(why) = shared.get() {
+                error!("{}. Retry in 5 seconds", why);
+                tokio::time::delay_for(Duration::from_secs(5)).await;
             }
-        };
+            manager.lock().await.forward_to_rori(utext).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            metrics::QUEUE_DEPTH.with_label_values(&[&*account_id, "to_rori"]).dec();
+        }
     }
 
     // Helpers
@@ -213,8 +282,9 @@ impl Endpoint {
      * @param main_info path or alias
      * @param password
      * @param from_archive if main_info is a path
+     * @return the id of the new account, empty if it fails
      */
-    pub fn add_account(main_info: &str, password: &str, from_archive: bool) {
+    pub async fn add_account(main_info: &str, password: &str, from_archive: bool) -> String {
         let mut details: HashMap<&str, &str> = HashMap::new();
         if from_archive {
             details.insert("Account.archivePath", main_info);
@@ -223,104 +293,194 @@ impl Endpoint {
         }
         details.insert("Account.type", "RING");
         details.insert("Account.archivePassword", password);
-        let details = Dict::new(details.iter());
-        let dbus_msg = Message::new_method_call("cx.ring.Ring", "/cx/ring/Ring/ConfigurationManager",
-                                                "cx.ring.Ring.ConfigurationManager",
-                                                "addAccount");
-        if !dbus_msg.is_ok() {
-            error!("addAccount fails. Please verify daemon's API.");
-            return;
-        }
-        let conn = Connection::get_private(BusType::Session);
-        if !conn.is_ok() {
-            return;
-        }
-        let dbus = conn.unwrap();
-        let response = dbus.send_with_reply_and_block(dbus_msg.unwrap()
-                                                                .append1(details), 2000).unwrap();
-        // addAccount returns one argument, which is a string.
-        let account_added: &str  = match response.get1() {
-            Some(account) => account,
-            None => ""
+        let conn = match connect() {
+            Ok(conn) => conn,
+            Err(why) => {
+                error!("{}", why);
+                return String::new();
+            }
+        };
+        let account_added = match configuration_manager!(DaemonBus::current(), &conn, add_account(details)) {
+            Ok(account) => account,
+            Err(why) => {
+                error!("{}. Please verify daemon's API.", why);
+                String::new()
+            }
         };
         info!("New account: {:?}", account_added);
+        account_added
     }
 
     /**
      * Get current ring accounts
      * @return current accounts
      */
-    pub fn get_account_list() -> Vec<Account> {
+    pub async fn get_account_list() -> Vec<Account> {
         let mut account_list: Vec<Account> = Vec::new();
-        let dbus_msg = Message::new_method_call("cx.ring.Ring", "/cx/ring/Ring/ConfigurationManager",
-                                                "cx.ring.Ring.ConfigurationManager",
-                                                "getAccountList");
-        if !dbus_msg.is_ok() {
-            error!("getAccountList fails. Please verify daemon's API.");
-            return account_list;
-        }
-        let conn = Connection::get_private(BusType::Session);
-        if !conn.is_ok() {
-            return account_list;
-        }
-        let dbus = conn.unwrap();
-        let response = dbus.send_with_reply_and_block(dbus_msg.unwrap(), 2000).unwrap();
-        // getAccountList returns one argument, which is an array of strings.
-        let accounts: Array<&str, _>  = match response.get1() {
-            Some(array) => array,
-            None => return account_list
+        let conn = match connect() {
+            Ok(conn) => conn,
+            Err(_) => return account_list,
+        };
+        let accounts = match configuration_manager!(DaemonBus::current(), &conn, get_account_list()) {
+            Ok(accounts) => accounts,
+            Err(why) => {
+                error!("{}. Please verify daemon's API.", why);
+                return account_list;
+            }
         };
         for account in accounts {
-            account_list.push(Endpoint::build_account(account));
+            account_list.push(Endpoint::build_account(&conn, &account).await);
         }
         account_list
     }
 
+    /**
+     * Check that the daemon answers on the session bus
+     * @return the number of accounts, or an error to show
+     */
+    pub async fn ping_daemon() -> Result<usize, String> {
+        let conn = connect()?;
+        let accounts = configuration_manager!(DaemonBus::current(), &conn, get_account_list())
+            .map_err(|e| format!("Is the daemon launched? {}", e))?;
+        Ok(accounts.len())
+    }
+
+    /**
+     * Get the volatile details of an account (e.g. Account.registrationStatus)
+     * @param account_id
+     * @return the details, or an error to show
+     */
+    pub async fn get_volatile_details(account_id: &str) -> Result<HashMap<String, String>, String> {
+        let conn = connect()?;
+        configuration_manager!(DaemonBus::current(), &conn, get_volatile_account_details(account_id))
+    }
+
+    /**
+     * Get the contacts of an account
+     * @param account_id
+     * @return the contacts (with "id" and "confirmed"), or an error to show
+     */
+    pub async fn get_contacts(account_id: &str) -> Result<Vec<HashMap<String, String>>, String> {
+        let conn = connect()?;
+        configuration_manager!(DaemonBus::current(), &conn, get_contacts(account_id))
+    }
+
+    /**
+     * Update the trusted contacts of an account, shown by the health endpoint
+     * @param account_id
+     */
+    pub async fn refresh_contacts(account_id: String) {
+        let contacts = Endpoint::get_contacts(&account_id).await.map(|contacts| {
+            contacts.iter()
+                .filter(|c| c.get("confirmed").map(|confirmed| confirmed == "true").unwrap_or(false))
+                .filter_map(|c| c.get("id").map(|id| id.to_lowercase()))
+                .collect()
+        });
+        health::set_contacts(&account_id, contacts);
+    }
+
+    /**
+     * Enable or disable a Ring account
+     * @param account_id
+     * @param enabled
+     * @return an error to show if any
+     */
+    pub async fn set_account_enabled(account_id: &str, enabled: bool) -> Result<(), String> {
+        let conn = connect()?;
+        configuration_manager!(DaemonBus::current(), &conn, set_account_enabled(account_id, enabled))
+    }
+
+    /**
+     * Export a Ring account to an archive
+     * @param account_id
+     * @param path of the archive
+     * @param password of the archive
+     * @return an error to show if any
+     */
+    pub async fn export_account(account_id: &str, path: &str, password: &str) -> Result<(), String> {
+        let conn = connect()?;
+        let exported = configuration_manager!(DaemonBus::current(), &conn, Duration::from_secs(10),
+            export_to_file(account_id, path, password))?;
+        if !exported {
+            return Err(format!("Can't export {} to {}", account_id, path));
+        }
+        Ok(())
+    }
+
+    /**
+     * Remove a Ring account from the daemon
+     * @param account_id
+     * @return an error to show if any
+     */
+    pub async fn remove_account(account_id: &str) -> Result<(), String> {
+        let conn = connect()?;
+        configuration_manager!(DaemonBus::current(), &conn, remove_account(account_id))
+    }
+
+    /**
+     * Register a public name for a Ring account and wait for the result
+     * @param account_id
+     * @param password of the account (empty if none)
+     * @param name to register
+     * @return an error to show if the name is not registered
+     */
+    pub async fn register_name(account_id: &str, password: &str, name: &str) -> Result<(), String> {
+        let conn = connect()?;
+        let bus = DaemonBus::current();
+        let msg_match = conn.add_match(bus.signal_rule("nameRegistrationEnded")).await
+            .map_err(|e| format!("Can't listen nameRegistrationEnded: {}", e))?;
+        let (_msg_match, mut results) = msg_match.stream::<NameRegistrationEnded>();
+        let accepted = configuration_manager!(bus, &conn, register_name(account_id, password, name))?;
+        if !accepted {
+            return Err(String::from("The daemon refused to register the name. Is it a Ring account?"));
+        }
+        // The result is given by nameRegistrationEnded
+        let wait_result = async {
+            while let Some((_, result)) = results.next().await {
+                if result.account_id == account_id {
+                    return Some(result.state);
+                }
+            }
+            None
+        };
+        match tokio::time::timeout(Duration::from_secs(60), wait_result).await {
+            Ok(Some(0)) => Ok(()),
+            Ok(Some(1)) => Err(String::from("Wrong password")),
+            Ok(Some(2)) => Err(format!("Invalid name: {}", name)),
+            Ok(Some(3)) => Err(format!("{} is already taken", name)),
+            Ok(Some(4)) => Err(String::from("Network error")),
+            Ok(Some(state)) => Err(format!("Unknown registration state {}", state)),
+            _ => Err(String::from("No answer from the daemon")),
+        }
+    }
+
 // Private stuff
     /**
      * Build a new account with an id from the daemon
+     * @param conn
      * @param id the account id to build
      * @return the account retrieven
      */
-    fn build_account(id: &str) -> Account {
-        let dbus_msg = Message::new_method_call("cx.ring.Ring", "/cx/ring/Ring/ConfigurationManager",
-                                                "cx.ring.Ring.ConfigurationManager",
-                                                "getAccountDetails");
-        if !dbus_msg.is_ok() {
-            error!("getAccountDetails fails. Please verify daemon's API.");
-            return Account::null();
-        }
-        let conn = Connection::get_private(BusType::Session);
-        if !conn.is_ok() {
-            error!("connection not ok.");
-            return Account::null();
-        }
-        let dbus = conn.unwrap();
-        let response = dbus.send_with_reply_and_block(
-                                           dbus_msg.unwrap().append1(id), 2000
-                                       ).ok().expect("Is the ring-daemon launched?");
-        let details: Dict<&str, &str, _> = match response.get1() {
-            Some(details) => details,
-            None => {
+    async fn build_account(conn: &Arc<SyncConnection>, id: &str) -> Account {
+        let details = match configuration_manager!(DaemonBus::current(), conn, get_account_details(id)) {
+            Ok(details) => details,
+            Err(why) => {
+                error!("{}. Is the daemon launched?", why);
                 return Account::null();
             }
         };
 
         let mut account = Account::null();
         account.id = id.to_owned();
-        for detail in details {
-            match detail {
-                (key, value) => {
-                    if key == "Account.enable" {
-                        account.enabled = value == "true";
-                    }
-                    if key == "Account.alias" {
-                        account.alias = String::from(value);
-                    }
-                    if key == "Account.username" {
-                        account.ring_id = String::from(value).replace("ring:", "");
-                    }
-                }
+        for (key, value) in details {
+            if key == "Account.enable" {
+                account.enabled = value == "true";
+            }
+            if key == "Account.alias" {
+                account.alias = value.clone();
+            }
+            if key == "Account.username" {
+                account.ring_id = value.replace("ring:", "");
             }
         }
         account
@@ -330,107 +490,170 @@ impl Endpoint {
      * Enable a Ring account
      * @param self
      */
-    pub fn enable_account(&self) {
-        let dbus_msg = Message::new_method_call(self.ring_dbus, self.configuration_path,
-                                                self.configuration_iface,
-                                                "sendRegister");
-        if !dbus_msg.is_ok() {
-            error!("sendRegister call fails. Please verify daemon's API.");
-            return;
+    pub async fn enable_account(&self) {
+        let registered: Result<(), String> = match self.conn.get() {
+            Ok(conn) => configuration_manager!(self.bus, &conn, send_register(&*self.account.id, true)),
+            Err(why) => Err(why),
+        };
+        if let Err(why) = registered {
+            error!("{}. Please verify daemon's API.", why);
         }
-        let conn = Connection::get_private(BusType::Session);
-        if !conn.is_ok() {
+    }
+
+    /**
+     * Handle a signal from the daemon
+     * @param self
+     * @param signal decoded by the listener
+     * @param rori_text where to send messages from RORI
+     */
+    fn handle_signal(&mut self, signal: Signal, rori_text: &RoriSay) {
+        match signal {
+            Signal::AccountsChanged => self.handle_accounts_signals(),
+            Signal::RegistrationStateChanged(signal) => self.handle_registration_changed(signal),
+            Signal::IncomingAccountMessage(signal) => {
+                let (account_id, msg_id, interaction) = self.handle_interactions(signal);
+                info!("New interation for {}: {}", account_id, interaction);
+                let mut channel = String::new();
+                if interaction.metadatas.contains_key("ch") {
+                    channel = interaction.metadatas["ch"].clone();
+                }
+                // Only the RORI handling the channel can talk in it
+                if account_id == self.account.id && self.is_rori(&interaction.author_ring_id, &channel) {
+                    // TODO forward all interaction.
+                    // RORI can relay messages of other Ring users ("from": their Ring id)
+                    let author = interaction.metadatas.get("from").cloned().unwrap_or_default();
+                    let rori_msg = DiscordMsg {
+                        id: msg_id,
+                        body: interaction.body,
+                        author,
+                        channel,
+                        datatype: interaction.datatype,
+                        metadatas: interaction.metadatas,
+                    };
+                    if rori_text.send((self.account.id.clone(), rori_msg)).is_err() {
+                        error!("The bot is stopped, message from RORI dropped");
+                        return;
+                    }
+                    metrics::QUEUE_DEPTH.with_label_values(&[&*self.account.id, "from_rori"]).inc();
+                }
+            },
+            Signal::IncomingTrustRequest(signal) => {
+                if signal.account_id == self.account.id {
+                    info!("New request from {}", signal.from);
+                    // TODO
+                    tokio::spawn(Endpoint::refresh_contacts(signal.account_id));
+                }
+            },
+            Signal::ContactAdded(ContactAdded { account_id, .. })
+            | Signal::ContactRemoved(ContactRemoved { account_id, .. }) => {
+                if account_id == self.account.id {
+                    tokio::spawn(Endpoint::refresh_contacts(account_id));
+                }
+            },
+        }
+    }
+
+    /**
+     * Send a message from Discord to RORI
+     * @param self
+     * @param utext
+     */
+    async fn forward_to_rori(&mut self, utext: DiscordMsg) {
+        if utext.body.is_empty() && utext.datatype != "discord/delete" {
             return;
         }
-        let dbus = conn.unwrap();
-        let _ = dbus.send_with_reply_and_block(
-            dbus_msg.unwrap().append2(self.account.id.clone(), true), 2000);
+        let mut datatype = &*utext.datatype;
+        if datatype == "text/plain" && self.is_a_command(&utext.body) {
+            datatype = "rori/command";
+        }
+        let mut payloads: HashMap<&str, &str> = HashMap::new();
+        payloads.insert(datatype, &*utext.body);
+        payloads.insert("sa", &*utext.author);
+        payloads.insert("th", &*utext.id);
+        payloads.insert("ch", &*utext.channel);
+        for (key, value) in &utext.metadatas {
+            payloads.insert(key, value);
+        }
+        self.send_interaction_to_rori(payloads).await;
     }
 
     /**
      * Update current RORI account by handling accountsChanged signals from daemon.
      * @param self
-     * @param ci
      */
-    fn handle_accounts_signals(&mut self, ci: &ConnectionItem) {
-        // Check signal
-        let msg = if let &ConnectionItem::Signal(ref signal) = ci { signal } else { return };
-        if &*msg.interface().unwrap() != "cx.ring.Ring.ConfigurationManager" { return };
-        if &*msg.member().unwrap() != "accountsChanged" { return };
+    fn handle_accounts_signals(&mut self) {
         // TODO test if RORI accounts is still exists
     }
 
     /**
     * Handle new interactions signals
     * @param self
-    * @param ci
-    * @return (accountId, interaction)
+    * @param signal
+    * @return (accountId, messageId, interaction)
     */
-    fn handle_interactions(&self, ci: &ConnectionItem) -> Option<(String, Interaction)> {
-        // Check signal
-        let msg = if let &ConnectionItem::Signal(ref signal) = ci { signal } else { return None };
-        if &*msg.interface().unwrap() != "cx.ring.Ring.ConfigurationManager" { return None };
-        if &*msg.member().unwrap() != "incomingAccountMessage" { return None };
-        // incomingAccountMessage return three arguments
-        let (account_id, _msg_id, author_ring_id, payloads) = msg.get4::<&str, &str, &str, Dict<&str, &str, _>>();
-        let author_ring_id = author_ring_id.unwrap().to_string();
+    fn handle_interactions(&self, signal: IncomingAccountMessage) -> (String, String, Interaction) {
         let mut body = String::new();
         let mut datatype = String::new();
         let mut metadatas: HashMap<String, String> = HashMap::new();
-        for detail in payloads.unwrap() {
-            match detail {
-                (key, value) => {
-                    // TODO for now, text/plain is the only supported datatypes, changes this with key in supported datatypes
-                    if key == "text/plain" {
-                        datatype = key.to_string();
-                        body = value.to_string();
-                    } else {
-                        metadatas.insert(
-                            key.to_string(),
-                            value.to_string()
-                        );
-                    }
-                }
+        for (key, value) in signal.payloads {
+            if SUPPORTED_DATATYPES.contains(&&*key) {
+                datatype = key;
+                body = value;
+            } else {
+                metadatas.insert(key, value);
             }
         };
         let interaction = Interaction {
-            author_ring_id: author_ring_id,
-            body: body,
-            datatype: datatype,
+            author_ring_id: signal.from,
+            body,
+            datatype,
             time: time::now(),
-            metadatas: metadatas
+            metadatas
         };
-        Some((account_id.unwrap().to_string(), interaction))
+        (signal.account_id, signal.message_id, interaction)
     }
 
     /**
      * Update current RORI account by handling accountsChanged signals from daemon
      * @param self
-     * @param ci
+     * @param signal
      */
-    fn handle_registration_changed(&self, ci: &ConnectionItem) {
-        // Check signal
-        let msg = if let &ConnectionItem::Signal(ref signal) = ci { signal } else { return };
-        if &*msg.interface().unwrap() != "cx.ring.Ring.ConfigurationManager" { return };
-        if &*msg.member().unwrap() != "registrationStateChanged" { return };
-        // let (account_id, registration_state, _, _) = msg.get4::<&str, &str, u64, &str>();
+    fn handle_registration_changed(&self, signal: RegistrationStateChanged) {
+        debug!("{} is now {}", signal.account_id, signal.registration_state);
+        if signal.account_id == self.account.id {
+            metrics::set_registration_state(&signal.account_id, &signal.registration_state);
+            health::set_registration(&signal.account_id, &signal.registration_state);
+        }
         // TODO the account can be disabled. Inform UI
     }
 
     /**
-     * Handle new pending requests signals
      * @param self
-     * @param ci
-     * @return (accountId, from)
+     * @param ring_id of an author
+     * @param channel where the author wants to talk
+     * @return if the author is the RORI handling this channel
+     */
+    fn is_rori(&self, ring_id: &str, channel: &str) -> bool {
+        let rori = self.channel_roris.get(channel).map(|(_, rori)| rori).unwrap_or(&self.rori_ring_id);
+        ring_id.replace("ring:", "").to_lowercase() == rori.replace("ring:", "").to_lowercase()
+    }
+
+    /**
+     * Get the RORI handling a guild or a channel
+     * @param self
+     * @param guild
+     * @param channel
+     * @return the ring id of the RORI
      */
-    fn handle_requests(&self, ci: &ConnectionItem) -> Option<(String, String)> {
-        // Check signal
-        let msg = if let &ConnectionItem::Signal(ref signal) = ci { signal } else { return None };
-        if &*msg.interface().unwrap() != "cx.ring.Ring.ConfigurationManager" { return None };
-        if &*msg.member().unwrap() != "incomingTrustRequest" { return None };
-        // incomingTrustRequest return three arguments
-        let (account_id, from, _, _) = msg.get4::<&str, &str, Dict<&str, &str, _>, u64>();
-        Some((account_id.unwrap().to_string(), from.unwrap().to_string()))
+    fn get_rori(&self, guild: &str, channel: &str) -> String {
+        if !guild.is_empty() {
+            for key in &[format!("{}/{}", guild, channel), String::from(guild)] {
+                if let Some(rori) = self.rori_overrides.get(key) {
+                    return rori.clone();
+                }
+            }
+        }
+        self.rori_ring_id.clone()
     }
 
     /**
@@ -441,9 +664,9 @@ impl Endpoint {
      * @param text to verify
      * @return true if it's a correct command
      */
-    fn is_a_command(&self, text: &String) -> bool {
+    fn is_a_command(&self, text: &str) -> bool {
         let v: Vec<&str> = text.split(' ').collect();
-        if v.len() == 0 {
+        if v.is_empty() {
             return false
         }
         let whitelist_commands = ["/register", "/unregister",
@@ -458,27 +681,73 @@ impl Endpoint {
      * @param body text to send
      * @return the interaction id if success. TODO, watch message status (if received)
      */
-    fn send_interaction_to_rori(&self, payloads: HashMap<&str, &str>) -> u64 {
-        let payloads = Dict::new(payloads.iter());
-
-        let dbus_msg = Message::new_method_call(self.ring_dbus, self.configuration_path, self.configuration_iface,
-                                                "sendTextMessage");
-        if !dbus_msg.is_ok() {
-            error!("sendTextMessage fails. Please verify daemon's API.");
-            return 0;
+    async fn send_interaction_to_rori(&mut self, payloads: HashMap<&str, &str>) -> u64 {
+        let guild = payloads.get("gd").cloned().unwrap_or("");
+        let channel = payloads.get("ch").cloned().unwrap_or("");
+        let rori_ring_id = self.get_rori(guild, channel);
+        if !channel.is_empty() {
+            self.channel_roris.insert(String::from(channel), (String::from(guild), rori_ring_id.clone()));
         }
-        let conn = Connection::get_private(BusType::Session);
-        if !conn.is_ok() {
-            return 0;
-        }
-        let dbus = conn.unwrap();
-        let response = dbus.send_with_reply_and_block(dbus_msg.unwrap().append3(&*self.account.id,
-            self.rori_ring_id.clone(), payloads), 2000).unwrap();
-        // sendTextMessage returns one argument, which is a u64.
-        let interaction_id: u64  = match response.get1() {
-            Some(interaction_id) => interaction_id,
-            None => 0
+        let sent = match self.conn.get() {
+            Ok(conn) => configuration_manager!(self.bus, &conn, send_text_message(&*self.account.id, &*rori_ring_id, payloads)),
+            Err(why) => Err(why),
         };
-        interaction_id
+        match sent {
+            Ok(interaction_id) => interaction_id,
+            Err(why) => {
+                error!("{}. Please verify daemon's API.", why);
+                0
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rori::daemon::RING;
+
+    fn endpoint(overrides: &[(&str, &str)]) -> Endpoint {
+        Endpoint {
+            account: Account::null(),
+            rori_ring_id: String::from("default"),
+            rori_overrides: overrides.iter().map(|(k, v)| (String::from(*k), String::from(*v))).collect(),
+            channel_roris: HashMap::new(),
+            bus: &RING,
+            conn: DaemonConnection::shared(),
+        }
+    }
+
+    #[test]
+    fn channel_rori_before_guild_rori_before_default() {
+        let endpoint = endpoint(&[("1", "guild"), ("1/10", "channel")]);
+        assert_eq!(endpoint.get_rori("1", "10"), "channel");
+        assert_eq!(endpoint.get_rori("1", "11"), "guild");
+        assert_eq!(endpoint.get_rori("2", "10"), "default");
+        // Direct messages
+        assert_eq!(endpoint.get_rori("", "10"), "default");
+    }
+
+    #[test]
+    fn only_the_rori_of_the_channel_can_answer() {
+        let mut endpoint = endpoint(&[("1", "guild")]);
+        endpoint.channel_roris.insert(String::from("10"), (String::from("1"), String::from("guild")));
+        assert!(endpoint.is_rori("ring:GUILD", "10"));
+        assert!(!endpoint.is_rori("default", "10"));
+        assert!(endpoint.is_rori("default", "20"));
+        assert!(!endpoint.is_rori("guild", "20"));
+    }
+
+    #[test]
+    fn channels_follow_the_new_roris() {
+        let mut endpoint = endpoint(&[("1", "guild")]);
+        endpoint.channel_roris.insert(String::from("10"), (String::from("1"), String::from("guild")));
+        endpoint.channel_roris.insert(String::from("20"), (String::new(), String::from("default")));
+        let overrides = [(String::from("1/10"), String::from("channel"))].iter().cloned().collect();
+        endpoint.set_rori("new", overrides);
+        assert!(endpoint.is_rori("channel", "10"));
+        assert!(!endpoint.is_rori("guild", "10"));
+        assert!(endpoint.is_rori("new", "20"));
+        assert!(!endpoint.is_rori("default", "20"));
     }
 }
@@ -1,4 +1,4 @@
-/**
+/*
  * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
  * All rights reserved.
  * Redistribution and use in source and binary forms, with or without
@@ -26,5 +26,10 @@
  **/
 
 pub mod account;
+pub mod configurationmanager;
+#[macro_use]
+pub mod daemon;
 pub mod interaction;
 pub mod endpoint;
+pub mod nameserver;
+pub mod signals;
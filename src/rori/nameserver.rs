@@ -0,0 +1,363 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use crate::metrics;
+use reqwest;
+use reqwest::{Certificate, Client, StatusCode};
+use serde_json::{Value, from_str};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio;
+
+/**
+ * TLS settings used to talk with name servers
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    // PEM file with the CA bundle (or the self-signed certificate) of the name servers.
+    // Added to the system's roots
+    #[serde(default)]
+    pub ca_file: String,
+    // Accept invalid certificates. Only for local development
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+impl TlsConfig {
+    pub fn new() -> TlsConfig {
+        TlsConfig {
+            ca_file: String::new(),
+            insecure: false,
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+ * Timeouts, retries and cache of the name server client
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NameServerConfig {
+    // Seconds before a request fails
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    // Attempts after a network error or a server error
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    // Seconds to keep a resolved name or address. 0 to disable the cache
+    #[serde(default = "default_cache_ttl")]
+    pub cache_ttl: u64,
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+fn default_cache_ttl() -> u64 {
+    600
+}
+
+impl NameServerConfig {
+    pub fn new() -> NameServerConfig {
+        NameServerConfig {
+            timeout: default_timeout(),
+            retries: default_retries(),
+            cache_ttl: default_cache_ttl(),
+        }
+    }
+}
+
+impl Default for NameServerConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+ * How long a name or an address not found (or a failed request) is kept. Short,
+ * because a name can be registered later, but it avoids asking again for each message
+ */
+const NOT_FOUND_TTL: Duration = Duration::from_secs(60);
+
+/**
+ * Results of the name servers, kept for a while
+ */
+#[derive(Debug)]
+struct NameCache {
+    ttl: Duration,
+    // "nameserver/name/<name>" or "nameserver/addr/<ring id>" -> (result, expiration)
+    entries: HashMap<String, (String, Instant)>,
+}
+
+impl NameCache {
+    /**
+     * @param ttl how long results are kept. 0 to disable the cache
+     */
+    fn new(ttl: Duration) -> NameCache {
+        NameCache {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /**
+     * @param self
+     * @param url looked up
+     * @return the result if it is still valid
+     */
+    fn get(&self, url: &str) -> Option<String> {
+        match self.entries.get(url) {
+            Some((value, expiration)) if Instant::now() < *expiration => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    /**
+     * Keep a result. Not found values are kept for NOT_FOUND_TTL at most
+     * @param self
+     * @param url looked up
+     * @param value found, empty if not found
+     */
+    fn insert(&mut self, url: String, value: String) {
+        if self.ttl.as_secs() == 0 {
+            return;
+        }
+        let ttl = if value.is_empty() { self.ttl.min(NOT_FOUND_TTL) } else { self.ttl };
+        let now = Instant::now();
+        self.entries.retain(|_, (_, expiration)| now < *expiration);
+        self.entries.insert(url, (value, now + ttl));
+    }
+}
+
+/**
+ * Async client for the name servers (name -> Ring id and Ring id -> name)
+ * Clones share the same connections and cache
+ */
+#[derive(Debug, Clone)]
+pub struct NameServer {
+    client: Client,
+    retries: u32,
+    cache: Arc<Mutex<NameCache>>,
+}
+
+impl NameServer {
+    /**
+     * Build the client
+     * @param tls
+     * @param config
+     * @return the client, or an error to show
+     */
+    pub fn new(tls: &TlsConfig, config: &NameServerConfig) -> Result<NameServer, String> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout));
+        if !tls.ca_file.is_empty() {
+            for certificate in NameServer::read_certificates(&tls.ca_file)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if tls.insecure {
+            warn!("!!! TLS certificates of name servers are NOT verified (tls.insecure). Never use this in production !!!");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        let client = builder.build().map_err(|e| format!("Can't build the name server client: {}", e))?;
+        Ok(NameServer {
+            client,
+            retries: config.retries,
+            cache: Arc::new(Mutex::new(NameCache::new(Duration::from_secs(config.cache_ttl)))),
+        })
+    }
+
+    /**
+     * Resolve a name
+     * @param self
+     * @param nameserver
+     * @param name
+     * @return the Ring id, empty if not found
+     */
+    pub async fn get_ring_id(&self, nameserver: &str, name: &str) -> String {
+        self.lookup(nameserver, &format!("name/{}", name), |j| {
+            let addr = j["addr"].to_string();
+            if addr.len() > 4 {
+                return String::from(&addr[3..addr.len()-1]);
+            }
+            String::new()
+        }).await
+    }
+
+    /**
+     * Lookup the name of an address
+     * @param self
+     * @param nameserver
+     * @param ring_id
+     * @return the name, empty if not found
+     */
+    pub async fn get_username(&self, nameserver: &str, ring_id: &str) -> String {
+        self.lookup(nameserver, &format!("addr/{}", ring_id), |j| {
+            j["name"].as_str().unwrap_or("").to_string()
+        }).await
+    }
+
+    /**
+     * Get a value from the cache, or from the name server
+     * @param self
+     * @param nameserver
+     * @param path to get
+     * @param parse get the value from the answer
+     * @return the value, empty if not found
+     */
+    async fn lookup<F>(&self, nameserver: &str, path: &str, parse: F) -> String
+        where F: Fn(&Value) -> String {
+        let mut ns = String::from(nameserver);
+        if ns.find("http") != Some(0) {
+            ns = String::from("https://") + &*ns;
+        }
+        let url = format!("{}/{}", ns, path);
+        if let Some(value) = self.cache.lock().unwrap().get(&url) {
+            return value;
+        }
+        let start = Instant::now();
+        let answer = self.get(&url).await;
+        metrics::NAME_SERVER_SECONDS.with_label_values(&[if answer.is_some() { "found" } else { "not_found" }])
+            .observe(start.elapsed().as_secs_f64());
+        let value = match answer {
+            Some(j) => parse(&j),
+            None => String::new(),
+        };
+        self.cache.lock().unwrap().insert(url, value.clone());
+        value
+    }
+
+    /**
+     * Get an url, with retries for network and server errors
+     * @param self
+     * @param url
+     * @return the JSON answer if any
+     */
+    async fn get(&self, url: &str) -> Option<Value> {
+        let mut attempt = 0;
+        loop {
+            let error = match self.client.get(url).send().await {
+                Ok(res) if res.status().is_server_error() => format!("server error {}", res.status()),
+                Ok(res) if res.status() == StatusCode::NOT_FOUND => return None,
+                Ok(res) => match res.text().await {
+                    Ok(body) => return from_str(&body).ok(),
+                    Err(why) => why.to_string(),
+                },
+                Err(why) => why.to_string(),
+            };
+            if attempt >= self.retries {
+                error!("Can't get {}: {}", url, error);
+                return None;
+            }
+            attempt += 1;
+            warn!("Can't get {}: {}. Retry ({}/{})", url, error, attempt, self.retries);
+            tokio::time::delay_for(Duration::from_millis(500 * attempt as u64)).await;
+        }
+    }
+
+    /**
+     * Read all certificates of a PEM file
+     * @param path
+     * @return the certificates, or an error to show
+     */
+    fn read_certificates(path: &str) -> Result<Vec<Certificate>, String> {
+        let mut content = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|e| format!("Can't read {}: {}", path, e))?;
+        let end = "-----END CERTIFICATE-----";
+        let mut certificates = Vec::new();
+        for pem in content.split(end).filter(|pem| pem.contains("-----BEGIN CERTIFICATE-----")) {
+            let pem = format!("{}{}\n", pem.trim_start(), end);
+            certificates.push(Certificate::from_pem(pem.as_bytes())
+                .map_err(|e| format!("Incorrect certificate in {}: {}", path, e))?);
+        }
+        if certificates.is_empty() {
+            return Err(format!("No certificate in {}", path));
+        }
+        Ok(certificates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://ns.jami.net/name/rori";
+
+    #[test]
+    fn cache_hit() {
+        let mut cache = NameCache::new(Duration::from_secs(600));
+        assert_eq!(cache.get(URL), None);
+        cache.insert(String::from(URL), String::from("ring_id"));
+        assert_eq!(cache.get(URL), Some(String::from("ring_id")));
+        assert_eq!(cache.get("https://ns.jami.net/name/other"), None);
+    }
+
+    #[test]
+    fn cache_expires() {
+        let mut cache = NameCache::new(Duration::from_secs(600));
+        cache.insert(String::from(URL), String::from("ring_id"));
+        // Expire the entry instead of waiting for it
+        cache.entries.get_mut(URL).unwrap().1 = Instant::now();
+        assert_eq!(cache.get(URL), None);
+        // Expired entries are dropped on insert
+        cache.insert(String::from("https://ns.jami.net/name/other"), String::from("other_id"));
+        assert!(!cache.entries.contains_key(URL));
+    }
+
+    #[test]
+    fn cache_disabled() {
+        let mut cache = NameCache::new(Duration::from_secs(0));
+        cache.insert(String::from(URL), String::from("ring_id"));
+        assert_eq!(cache.get(URL), None);
+    }
+
+    #[test]
+    fn not_found_is_cached_shortly() {
+        let mut cache = NameCache::new(Duration::from_secs(600));
+        cache.insert(String::from(URL), String::new());
+        assert_eq!(cache.get(URL), Some(String::new()));
+        assert!(cache.entries[URL].1 <= Instant::now() + NOT_FOUND_TTL);
+        // Never longer than cache_ttl
+        let mut cache = NameCache::new(Duration::from_secs(10));
+        cache.insert(String::from(URL), String::new());
+        assert!(cache.entries[URL].1 <= Instant::now() + Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,177 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use dbus::Message;
+use dbus::arg::ReadAll;
+use dbus::message::MessageType;
+// Arguments are the same for both namespaces
+pub use super::configurationmanager::ring::{
+    ConfigurationManagerContactAdded as ContactAdded,
+    ConfigurationManagerContactRemoved as ContactRemoved,
+    ConfigurationManagerIncomingAccountMessage as IncomingAccountMessage,
+    ConfigurationManagerIncomingTrustRequest as IncomingTrustRequest,
+    ConfigurationManagerNameRegistrationEnded as NameRegistrationEnded,
+    ConfigurationManagerRegistrationStateChanged as RegistrationStateChanged,
+};
+
+/**
+ * Signals handled by the endpoint
+ */
+pub const MEMBERS: [&str; 6] = ["incomingAccountMessage", "incomingTrustRequest", "accountsChanged", "registrationStateChanged",
+                                "contactAdded", "contactRemoved"];
+
+/**
+ * A signal from the ConfigurationManager, with its arguments
+ */
+#[derive(Debug)]
+pub enum Signal {
+    AccountsChanged,
+    RegistrationStateChanged(RegistrationStateChanged),
+    IncomingAccountMessage(IncomingAccountMessage),
+    IncomingTrustRequest(IncomingTrustRequest),
+    ContactAdded(ContactAdded),
+    ContactRemoved(ContactRemoved),
+}
+
+impl Signal {
+    /**
+     * @param self
+     * @return the account of the signal, None for accountsChanged
+     */
+    pub fn account_id(&self) -> Option<&str> {
+        match self {
+            Signal::AccountsChanged => None,
+            Signal::RegistrationStateChanged(signal) => Some(&*signal.account_id),
+            Signal::IncomingAccountMessage(signal) => Some(&*signal.account_id),
+            Signal::IncomingTrustRequest(signal) => Some(&*signal.account_id),
+            Signal::ContactAdded(signal) => Some(&*signal.account_id),
+            Signal::ContactRemoved(signal) => Some(&*signal.account_id),
+        }
+    }
+}
+
+/**
+ * Decode a signal of the ConfigurationManager. Never panics, whatever the message
+ * @param msg
+ * @param iface interface of the ConfigurationManager
+ * @return the signal, None if it's not a signal handled, or an error if its arguments are unexpected
+ */
+pub fn decode(msg: &Message, iface: &str) -> Result<Option<Signal>, String> {
+    if msg.msg_type() != MessageType::Signal {
+        return Ok(None);
+    }
+    let (interface, member) = match (msg.interface(), msg.member()) {
+        (Some(interface), Some(member)) => (interface, member),
+        _ => return Ok(None),
+    };
+    if &*interface != iface {
+        return Ok(None);
+    }
+    let signal = match &*member {
+        "accountsChanged" => Signal::AccountsChanged,
+        "registrationStateChanged" => Signal::RegistrationStateChanged(read(msg, &member)?),
+        "incomingAccountMessage" => Signal::IncomingAccountMessage(read(msg, &member)?),
+        "incomingTrustRequest" => Signal::IncomingTrustRequest(read(msg, &member)?),
+        "contactAdded" => Signal::ContactAdded(read(msg, &member)?),
+        "contactRemoved" => Signal::ContactRemoved(read(msg, &member)?),
+        _ => return Ok(None),
+    };
+    Ok(Some(signal))
+}
+
+/**
+ * @param msg
+ * @param member of the signal
+ * @return the arguments of the signal, or an error to show
+ */
+pub fn read<T: ReadAll>(msg: &Message, member: &str) -> Result<T, String> {
+    T::read(&mut msg.iter_init())
+        .map_err(|e| format!("Unexpected arguments for {}: {}", member, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const IFACE: &str = "net.jami.daemon.ConfigurationManager";
+
+    fn signal(iface: &str, member: &str) -> Message {
+        Message::new_signal("/net/jami/daemon/ConfigurationManager", iface, member).unwrap()
+    }
+
+    #[test]
+    fn decode_incoming_account_message() {
+        let mut payloads = HashMap::new();
+        payloads.insert(String::from("text/plain"), String::from("hello"));
+        let msg = signal(IFACE, "incomingAccountMessage")
+            .append3("account", "message", "from")
+            .append1(payloads.clone());
+        match decode(&msg, IFACE) {
+            Ok(Some(Signal::IncomingAccountMessage(signal))) => {
+                assert_eq!(signal.account_id, "account");
+                assert_eq!(signal.message_id, "message");
+                assert_eq!(signal.from, "from");
+                assert_eq!(signal.payloads, payloads);
+            },
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_contact_added() {
+        let msg = signal(IFACE, "contactAdded").append3("account", "rori", true);
+        match decode(&msg, IFACE) {
+            Ok(Some(Signal::ContactAdded(signal))) => {
+                assert_eq!(signal.account_id, "account");
+                assert_eq!(signal.uri, "rori");
+                assert!(signal.confirmed);
+            },
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_wrong_signature() {
+        let msg = signal(IFACE, "incomingAccountMessage").append2("account", 42u32);
+        assert!(decode(&msg, IFACE).is_err());
+        let msg = signal(IFACE, "registrationStateChanged");
+        assert!(decode(&msg, IFACE).is_err());
+    }
+
+    #[test]
+    fn decode_other_signals() {
+        let msg = signal("cx.ring.Ring.ConfigurationManager", "incomingAccountMessage")
+            .append3("account", "message", "from")
+            .append1(HashMap::<String, String>::new());
+        assert!(decode(&msg, IFACE).unwrap().is_none());
+        let msg = signal(IFACE, "volatileDetailsChanged").append1("account");
+        assert!(decode(&msg, IFACE).unwrap().is_none());
+        assert!(matches!(decode(&signal(IFACE, "accountsChanged"), IFACE), Ok(Some(Signal::AccountsChanged))));
+    }
+}
@@ -0,0 +1,270 @@
+/*
+ * Copyright (c) 2018, Sébastien Blin <sebastien.blin@enconn.fr>
+ * All rights reserved.
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ * * Redistributions in binary form must reproduce the above copyright
+ *  notice, this list of conditions and the following disclaimer in the
+ *  documentation and/or other materials provided with the distribution.
+ * * Neither the name of the University of California, Berkeley nor the
+ *  names of its contributors may be used to endorse or promote products
+ *  derived from this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE REGENTS AND CONTRIBUTORS ``AS IS'' AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED
+ * WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE REGENTS AND CONTRIBUTORS BE LIABLE FOR ANY
+ * DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES
+ * (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES;
+ * LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND
+ * ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ * (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+ * SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ **/
+
+
+use crate::config::ConfigFile;
+use crate::rori::account::Account;
+use crate::rori::endpoint::Endpoint;
+use crate::rori::nameserver::{NameServer, NameServerConfig, TlsConfig};
+use clap::ArgMatches;
+use rpassword;
+use std::io::{stdin,stdout,Write};
+use std::path::Path;
+use std::time::Duration;
+use tokio;
+
+/**
+ * How to get the Ring account of the bot
+ */
+pub enum AccountChoice {
+    // Create a new account with an alias
+    Create { alias: String, password: String },
+    // Import an account from an archive
+    Import { path: String, password: String },
+    // Use an existing account, by alias, Ring id or account id
+    Existing(String),
+    // Use the only existing account
+    Any,
+}
+
+/**
+ * Answers needed to generate a config file
+ */
+pub struct SetupAnswers {
+    pub discord_secret_token: String,
+    // Daemon to use: "ring", "jami" or "auto"
+    pub daemon: String,
+    pub rori_server: String,
+    pub rori_name: String,
+    pub tls: TlsConfig,
+    pub account: AccountChoice,
+    // Public name to register for the account. Empty to skip
+    pub register_name: String,
+    // Password of the account, to register the name
+    pub password: String,
+}
+
+/**
+ * Generate a config file from the answers
+ * @param answers
+ * @param path of the config file to write
+ * @return an error to show if any
+ */
+pub async fn setup(answers: SetupAnswers, path: &Path) -> Result<(), String> {
+    if answers.discord_secret_token.is_empty() {
+        return Err(String::from("A Discord token is required"));
+    }
+    let name_server = NameServer::new(&answers.tls, &NameServerConfig::new())?;
+    let rori_ring_id = name_server.get_ring_id(&answers.rori_server, &answers.rori_name).await;
+    if rori_ring_id.is_empty() {
+        return Err(format!("Cannot resolve {} on {}", answers.rori_name, answers.rori_server));
+    }
+
+    let ring_id = match answers.account {
+        AccountChoice::Create { alias, password } => create_account(&alias, &password, false).await?,
+        AccountChoice::Import { path, password } => create_account(&path, &password, true).await?,
+        AccountChoice::Existing(wanted) => {
+            let accounts = Endpoint::get_account_list().await;
+            match find_account(&accounts, &wanted) {
+                Some(account) => account.id.clone(),
+                None => return Err(format!("No account {}. Available accounts:\n{}", wanted, list_accounts(&accounts))),
+            }
+        },
+        AccountChoice::Any => {
+            let accounts = Endpoint::get_account_list().await;
+            if accounts.len() != 1 {
+                return Err(format!("Please choose an account with --account. Available accounts:\n{}", list_accounts(&accounts)));
+            }
+            accounts[0].id.clone()
+        },
+    };
+
+    let registration = if answers.register_name.is_empty() {
+        Ok(())
+    } else {
+        Endpoint::register_name(&ring_id, &answers.password, &answers.register_name).await
+    };
+
+    let mut config = ConfigFile::new();
+    config.discord_secret_token = answers.discord_secret_token;
+    config.daemon = answers.daemon;
+    if registration.is_ok() {
+        config.ring_name = answers.register_name;
+    }
+    config.ring_id = ring_id;
+    config.rori_server = answers.rori_server;
+    config.rori_ring_id = rori_ring_id;
+    config.rori_name = answers.rori_name;
+    config.tls = answers.tls;
+    config.save(path)?;
+    println!("{} written", path.display());
+    registration.map_err(|why| format!("Name not registered: {}", why))
+}
+
+/**
+ * Read answers from the setup subcommand
+ * @param matches of the setup subcommand
+ * @return the answers
+ */
+pub fn answers_from_args(matches: &ArgMatches) -> SetupAnswers {
+    let password = String::from(matches.value_of("password").unwrap_or(""));
+    let account = if let Some(alias) = matches.value_of("create-account") {
+        AccountChoice::Create { alias: String::from(alias), password }
+    } else if let Some(path) = matches.value_of("import-archive") {
+        AccountChoice::Import { path: String::from(path), password }
+    } else if let Some(account) = matches.value_of("account") {
+        AccountChoice::Existing(String::from(account))
+    } else {
+        AccountChoice::Any
+    };
+    SetupAnswers {
+        discord_secret_token: String::from(matches.value_of("token").unwrap_or("")),
+        daemon: String::from(matches.value_of("daemon").unwrap_or("auto")),
+        rori_server: String::from(matches.value_of("rori-server").unwrap_or("")),
+        rori_name: String::from(matches.value_of("rori-name").unwrap_or("rori")),
+        tls: TlsConfig {
+            ca_file: String::from(matches.value_of("ca-file").unwrap_or("")),
+            insecure: matches.is_present("insecure"),
+        },
+        account,
+        register_name: String::from(matches.value_of("register-name").unwrap_or("")),
+        password: String::from(matches.value_of("password").unwrap_or("")),
+    }
+}
+
+/**
+ * Ask answers on stdin
+ * @param daemon given with --daemon, "auto" if not given
+ * @return the answers
+ */
+pub async fn answers_from_stdin(daemon: &str) -> SetupAnswers {
+    println!("Please answer to following questions:");
+    let discord_secret_token = ask_password("Discord secret token: ");
+    let rori_server = ask("RORI server: ");
+    let mut rori_name = ask("Name of RORI (default: rori): ");
+    if rori_name.is_empty() {
+        rori_name = String::from("rori");
+    }
+    let mut tls = TlsConfig::new();
+    tls.ca_file = ask("CA file of the server (optional): ");
+    if tls.ca_file.is_empty() {
+        tls.insecure = ask("Accept invalid certificates (insecure)? y/N: ").to_lowercase() == "y";
+    }
+
+    let mut account = AccountChoice::Any;
+    let mut password = String::new();
+    if ask("Create an account? y/N: ").to_lowercase() == "y" {
+        let from_archive = ask("Import archive? y/N: ").to_lowercase() == "y";
+        let main_info = if from_archive { ask("path: ") } else { ask("alias: ") };
+        password = ask_password("password (optional): ");
+        account = if from_archive {
+            AccountChoice::Import { path: main_info, password: password.clone() }
+        } else {
+            AccountChoice::Create { alias: main_info, password: password.clone() }
+        };
+    } else {
+        let accounts = Endpoint::get_account_list().await;
+        println!("Choose an account:");
+        for (idx, account) in accounts.iter().enumerate() {
+            println!("{}. {}", idx, account);
+        }
+        let choice = ask("Your choice: ").parse::<usize>().unwrap_or(0);
+        if let Some(chosen) = accounts.get(choice) {
+            account = AccountChoice::Existing(chosen.id.clone());
+        }
+    }
+    let register_name = ask("Public name to register (optional): ");
+    if !register_name.is_empty() && password.is_empty() {
+        password = ask_password("password of the account (optional): ");
+    }
+
+    SetupAnswers {
+        discord_secret_token,
+        daemon: String::from(daemon),
+        rori_server,
+        rori_name,
+        tls,
+        account,
+        register_name,
+        password,
+    }
+}
+
+/**
+ * Find an account by alias, Ring id or account id
+ * @param accounts
+ * @param wanted
+ * @return the account if found
+ */
+pub fn find_account<'a>(accounts: &'a [Account], wanted: &str) -> Option<&'a Account> {
+    let wanted = wanted.replace("ring:", "");
+    accounts.iter().find(|account| account.id == wanted || account.ring_id == wanted || account.alias == wanted)
+}
+
+pub fn list_accounts(accounts: &[Account]) -> String {
+    accounts.iter().map(|account| format!("  {}", account)).collect::<Vec<String>>().join("\n")
+}
+
+/**
+ * Add an account to the daemon
+ * @param main_info alias or path
+ * @param password
+ * @param from_archive if main_info is a path
+ * @return the account id
+ */
+pub async fn create_account(main_info: &str, password: &str, from_archive: bool) -> Result<String, String> {
+    let account_id = Endpoint::add_account(main_info, password, from_archive).await;
+    if account_id.is_empty() {
+        return Err(String::from("Cannot create the account. Is the daemon launched?"));
+    }
+    // Let some time for the daemon
+    tokio::time::delay_for(Duration::from_millis(3000)).await;
+    Ok(account_id)
+}
+
+fn clean_string(string: String) -> String {
+    let mut s = string.clone();
+    if let Some('\n') = s.chars().next_back() {
+        s.pop();
+    }
+    if let Some('\r') = s.chars().next_back() {
+        s.pop();
+    }
+    s
+}
+
+pub fn ask(question: &str) -> String {
+    print!("{}", question);
+    let _ = stdout().flush();
+    let mut s = String::new();
+    stdin().read_line(&mut s).expect("Did not enter a correct string");
+    clean_string(s)
+}
+
+pub fn ask_password(question: &str) -> String {
+    rpassword::read_password_from_tty(Some(question)).expect("Did not enter a correct string")
+}